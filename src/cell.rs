@@ -0,0 +1,540 @@
+use runic::*;
+
+use mathml;
+use latex;
+use editor::{self, Cursor};
+use syntax::{self, Palette};
+use markup::{self, Style};
+use plot::Figure;
+use surface::Surface;
+
+use std::time::{Duration, Instant};
+
+/// Gap between the `(%iN)` label and the start of the input text, which holds the evaluation indicator
+const PROMPT_GAP: f32 = 16.0;
+
+/// Width kept clear to the right of an evaluated cell's input for how long it took
+const TIME_W: f32 = 80.0;
+
+#[derive(Clone)]
+pub struct Fonts {
+    pub code: Font,
+    pub math: Font,
+    pub text: Font,
+    pub title: Font,
+    pub section: Font,
+    pub subsection: Font
+}
+
+impl Fonts {
+    pub fn new(rx: &mut RenderContext) -> Result<Fonts, Box<::std::error::Error>> {
+        Ok(Fonts {
+            code: rx.new_font("Fira Code", 18.0, FontWeight::Regular, FontStyle::Normal)?,
+            math: rx.new_font("Cambria Math", 18.0, FontWeight::Regular, FontStyle::Normal)?,
+            text: rx.new_font("Cambria", 18.0, FontWeight::Regular, FontStyle::Normal)?,
+            title: rx.new_font("Cambria", 32.0, FontWeight::Bold, FontStyle::Normal)?,
+            section: rx.new_font("Cambria", 26.0, FontWeight::Bold, FontStyle::Normal)?,
+            subsection: rx.new_font("Cambria", 22.0, FontWeight::Bold, FontStyle::Normal)?
+        })
+    }
+
+    fn for_kind(&self, kind: CellKind) -> &Font {
+        match kind {
+            CellKind::Code => &self.code,
+            CellKind::Text => &self.text,
+            CellKind::Title => &self.title,
+            CellKind::Section => &self.section,
+            CellKind::Subsection => &self.subsection
+        }
+    }
+}
+
+/// What a cell holds. Only code cells are sent to Maxima; the rest document the worksheet
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CellKind {
+    Code, Text, Title, Section, Subsection
+}
+
+impl CellKind {
+    /// headings nest by level, with the title outermost
+    pub fn heading_level(&self) -> Option<usize> {
+        match *self {
+            CellKind::Title => Some(0),
+            CellKind::Section => Some(1),
+            CellKind::Subsection => Some(2),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CellKind::Code => "code",
+            CellKind::Text => "text",
+            CellKind::Title => "title",
+            CellKind::Section => "section",
+            CellKind::Subsection => "subsection"
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<CellKind> {
+        match s {
+            "code" => Some(CellKind::Code),
+            "text" => Some(CellKind::Text),
+            "title" => Some(CellKind::Title),
+            "section" => Some(CellKind::Section),
+            "subsection" => Some(CellKind::Subsection),
+            _ => None
+        }
+    }
+}
+
+/// Where a code cell is in being evaluated by Maxima. Cells go Idle -> Queued when asked to
+/// evaluate, Queued -> Running when sent, and Running -> Done or Failed at the next prompt
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EvalState {
+    Idle, Queued, Running, Done, Failed
+}
+
+/// seconds with a fractional part, as shown and as stored in notebooks
+pub fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+pub struct Cell {
+    pub kind: CellKind,
+    pub state: EvalState,
+    started: Option<Instant>,
+    /// wall time taken by the last evaluation
+    pub elapsed: Option<Duration>,
+    time_layout: Option<TextLayout>,
+    /// for headings, whether the cells under it are hidden
+    pub collapsed: bool,
+    /// the `%i`/`%o` label this cell was last evaluated under, if it has been evaluated
+    pub index: Option<usize>,
+    pub input: String,
+    pub output: Option<mathml::MathExpression>,
+    pub output_src: Option<String>,
+    output_stale: bool,
+    pub output_linear: Option<String>,
+    pub output_tex: Option<String>,
+    /// a plot made by the last evaluation, drawn under the output
+    pub plot: Option<Figure>,
+    /// the surface a 3D plot is drawn from, which is drawn again whenever it is turned
+    pub surface: Option<Surface>,
+    /// a problem with the input that kept it from being evaluated
+    warning: Option<String>,
+    warning_layout: Option<TextLayout>,
+    /// the subexpression of the output that has been clicked on, as a path from `MathExpression::hit`
+    pub output_selection: Option<Vec<usize>>,
+    prompt_layout: Option<TextLayout>,
+    pub input_layout: Option<TextLayout>,
+    layout_width: f32,
+    /// height when last drawn
+    height: Option<f32>,
+    pub pos: Point
+}
+
+impl Cell {
+    pub fn empty(index: Option<usize>) -> Cell {
+        Cell {
+            kind: CellKind::Code, collapsed: false, state: EvalState::Idle,
+            started: None, elapsed: None, time_layout: None,
+            index, input: String::new(),
+            output: None, output_src: None, output_stale: false,
+            output_linear: None, output_tex: None, output_selection: None, plot: None, surface: None,
+            warning: None, warning_layout: None,
+            prompt_layout: None, input_layout: None, layout_width: 0.0, height: None,
+            pos: Point::xy(0.0, 0.0)
+        }
+    }
+
+    /// a new unevaluated cell holding some input
+    pub fn with_input(input: String) -> Cell {
+        let mut c = Cell::empty(None);
+        c.input = input;
+        c
+    }
+
+    pub fn prompt(&self) -> String {
+        match (self.kind, self.index) {
+            (CellKind::Code, Some(i)) => format!("(%i{})", i),
+            (CellKind::Code, None) => String::from("-->"),
+            (CellKind::Text, _) => String::new(),
+            (_, _) => String::from(if self.collapsed { "▸" } else { "▾" })
+        }
+    }
+
+    /// change what kind of cell this is, returning what it was before
+    pub fn set_kind(&mut self, kind: CellKind) -> CellKind {
+        let old = self.kind;
+        self.kind = kind;
+        self.prompt_layout = None;
+        self.input_layout = None;
+        old
+    }
+
+    pub fn toggle_collapsed(&mut self) {
+        if self.kind.heading_level().is_some() {
+            self.collapsed = !self.collapsed;
+            self.prompt_layout = None;
+        }
+    }
+
+    /// relabel the cell, as when it is re-evaluated at a later prompt
+    pub fn set_index(&mut self, index: usize) {
+        self.index = Some(index);
+        self.prompt_layout = None;
+        self.input_layout = None;
+    }
+
+    pub fn set_output(&mut self, src: String, linear: Option<String>, tex: Option<String>) {
+        self.output_src = Some(src);
+        self.output_linear = linear;
+        self.output_tex = tex;
+        self.output = None;
+        self.output_selection = None;
+        self.output_stale = true;
+    }
+
+    /// ask for the cell to be evaluated. Only code cells that aren't already waiting on Maxima can be
+    pub fn queue(&mut self) -> bool {
+        match self.state {
+            EvalState::Idle | EvalState::Done | EvalState::Failed if self.kind == CellKind::Code => {
+                self.state = EvalState::Queued;
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// the cell's input has been sent to Maxima
+    pub fn start(&mut self) {
+        if self.state == EvalState::Queued {
+            self.state = EvalState::Running;
+            self.started = Some(Instant::now());
+            self.plot = None;
+            self.surface = None;
+        }
+    }
+
+    /// Maxima has finished with the cell's input and is back at a prompt
+    pub fn finish(&mut self, failed: bool) {
+        if self.state == EvalState::Running {
+            self.state = if failed { EvalState::Failed } else { EvalState::Done };
+            self.elapsed = self.started.take().map(|t| t.elapsed());
+            self.time_layout = None;
+        }
+    }
+
+    /// take the cell back out of the queue
+    pub fn cancel(&mut self) {
+        if self.state == EvalState::Queued {
+            self.state = EvalState::Idle;
+        }
+    }
+
+    pub fn set_elapsed(&mut self, d: Duration) {
+        self.elapsed = Some(d);
+        self.time_layout = None;
+    }
+
+    pub fn set_plot(&mut self, plot: Figure) {
+        self.plot = Some(plot);
+        self.surface = None;
+        self.height = None;
+    }
+
+    pub fn set_surface(&mut self, surface: Surface) {
+        self.set_plot(surface.figure());
+        self.surface = Some(surface);
+    }
+
+    /// Change how a 3D plot is viewed, and draw it again from there. The figure is only made
+    /// again if the view actually moved, since that means projecting and sorting every cell of it
+    pub fn view_surface<F: FnOnce(&mut Surface)>(&mut self, f: F) {
+        if let Some(ref mut s) = self.surface {
+            let before = (s.azimuth, s.elevation, s.zoom);
+            f(s);
+            if (s.azimuth, s.elevation, s.zoom) != before {
+                self.plot = Some(s.figure());
+            }
+        }
+    }
+
+    pub fn set_warning(&mut self, warning: Option<String>) {
+        if self.warning != warning {
+            self.warning = warning;
+            self.warning_layout = None;
+        }
+    }
+
+    pub fn latex(&self) -> Option<String> {
+        self.output.as_ref().map(|o| latex::from_element(o.root()))
+    }
+
+    fn prompt_bounds(&self) -> Rect {
+        self.prompt_layout.as_ref().map(|ly| ly.bounds()).unwrap_or(Rect::wh(0.0, 0.0))
+    }
+
+    /// where the input text starts, to the right of the prompt label
+    fn input_origin(&self) -> Point {
+        self.pos + Point::x(self.prompt_bounds().w + PROMPT_GAP)
+    }
+
+    /// height of the input, along with any warning under it
+    fn input_height(&self) -> f32 {
+        let ih = self.input_layout.as_ref().map(|ly| ly.bounds().h).unwrap_or(0.0).max(self.prompt_bounds().h);
+        ih + self.warning_layout.as_ref().map(|ly| ly.bounds().h + 2.0).unwrap_or(0.0)
+    }
+
+    /// where the output is drawn from, which is the middle of its left edge
+    fn output_origin(&self) -> Point {
+        let ih = self.input_height();
+        let oh = self.output.as_ref().map(|o| o.bounds().h).unwrap_or(0.0);
+        self.pos + Point::y(ih+4.0 + oh/2.0)
+    }
+
+    /// where the plot is drawn from, which is its top left corner
+    fn plot_origin(&self) -> Point {
+        let oh = self.output.as_ref().map(|o| o.bounds().h + 4.0).unwrap_or(0.0);
+        self.pos + Point::y(self.input_height() + 4.0 + oh)
+    }
+
+    /// is the window point `p` over the plot?
+    pub fn hit_plot(&self, p: Point) -> bool {
+        self.plot.as_ref().map_or(false, |f| f.bounds().offset(self.plot_origin()).contains(p))
+    }
+
+    /// is the window point `p` over the label?
+    pub fn hit_prompt(&self, p: Point) -> bool {
+        self.prompt_bounds().offset(self.pos).contains(p)
+    }
+
+    /// the subexpressions of the output under the window point `p`, outermost first
+    pub fn hit_output(&self, p: Point) -> Vec<(Vec<usize>, Rect)> {
+        let op = self.output_origin();
+        self.output.as_ref().map(|o| o.hit(p - op)).unwrap_or(Vec::new())
+    }
+
+    /// the area covered by a subexpression of the output, in window coordinates
+    pub fn output_rect(&self, path: &[usize]) -> Option<Rect> {
+        let op = self.output_origin();
+        self.output.as_ref().and_then(|o| o.element(path)).map(|(_, r)| r.offset(op))
+    }
+
+    /// the subexpression of the output that has been clicked on
+    pub fn selected_output(&self) -> Option<&mathml::Element> {
+        match (self.output.as_ref(), self.output_selection.as_ref()) {
+            (Some(o), Some(path)) => o.element(path).map(|(e, _)| e),
+            _ => None
+        }
+    }
+
+    /// the height the cell took up when it was last drawn, or a guess from its line count if it hasn't been yet
+    pub fn height(&self) -> f32 {
+        self.height.unwrap_or_else(|| {
+            let lines = self.input.lines().count().max(1) as f32;
+            lines * 24.0 + if self.output_src.is_some() { 32.0 } else { 4.0 }
+                + self.plot.as_ref().map(|f| f.bounds().h + 4.0).unwrap_or(0.0)
+        })
+    }
+
+    pub fn bounds(&self) -> Rect {
+       let pb = self.prompt_bounds();
+       let ib = self.input_layout.as_ref().map(|ly| ly.bounds()).unwrap_or(Rect::wh(0.0, 0.0));
+       let ob = self.output.as_ref().map(|e| e.bounds()).unwrap_or(Rect::wh(0.0, 0.0));
+       let fb = self.plot.as_ref().map(|f| f.bounds()).unwrap_or(Rect::wh(0.0, 0.0));
+       let ih = self.input_height();
+       let fh = if self.plot.is_some() { fb.h + 4.0 } else { 0.0 };
+       Rect::wh((pb.w + PROMPT_GAP + ib.w).max(ob.w).max(fb.w), ih+ob.h+4.0+fh)
+    }
+
+    /// draw the cell at `p`, wrapping the input so that it fits within `width`
+    pub fn draw(&mut self, p: Point, width: f32, rx: &mut RenderContext, fonts: &Fonts, palette: &Palette) {
+        self.pos = p;
+        let fnt = fonts.for_kind(self.kind);
+        if self.prompt_layout.is_none() {
+            self.prompt_layout = Some(rx.new_text_layout(&self.prompt(), fnt, 256.0, 256.0).expect("create text layout"));
+        }
+        let time_w = if self.elapsed.is_some() { TIME_W } else { 0.0 };
+        let input_w = (width - self.prompt_bounds().w - PROMPT_GAP - time_w).max(64.0);
+        if self.input_layout.is_none() || self.layout_width != input_w {
+            let mut ly = rx.new_text_layout(&self.input, fnt, input_w, 4096.0).expect("create text layout");
+            if self.kind == CellKind::Code {
+                for tok in syntax::lex(&self.input) {
+                    if let Some(col) = palette.color(tok.kind) {
+                        let start = editor::utf16_offset(&self.input, tok.range.start) as u32;
+                        let end = editor::utf16_offset(&self.input, tok.range.end) as u32;
+                        ly.color_range(start..end, col);
+                    }
+                }
+            } else if self.kind == CellKind::Text {
+                let input = &self.input;
+                let at = |i: usize| editor::utf16_offset(input, i) as u32;
+                for span in markup::parse(input) {
+                    let inner = span.inner();
+                    match span.style {
+                        Style::Bold => ly.weight_range(at(inner.start)..at(inner.end), FontWeight::Bold),
+                        Style::Italic => ly.style_range(at(inner.start)..at(inner.end), FontStyle::Italic),
+                        Style::Code => for tok in syntax::lex(&input[inner.clone()]) {
+                            if let Some(col) = palette.color(tok.kind) {
+                                ly.color_range(at(inner.start + tok.range.start)..at(inner.start + tok.range.end), col);
+                            }
+                        }
+                    }
+                    // the markers stay in the text, but faded
+                    let faded = Color::rgb(0.45, 0.45, 0.5);
+                    ly.color_range(at(span.range.start)..at(inner.start), faded);
+                    ly.color_range(at(inner.end)..at(span.range.end), faded);
+                }
+            }
+            self.input_layout = Some(ly);
+            self.layout_width = input_w;
+        }
+        rx.draw_text_layout(p, self.prompt_layout.as_ref().unwrap());
+        self.draw_status(rx, fonts);
+        rx.draw_text_layout(self.input_origin(), self.input_layout.as_ref().unwrap());
+        if let Some(ref w) = self.warning {
+            if self.warning_layout.is_none() {
+                self.warning_layout = rx.new_text_layout(w, &fonts.text, input_w, 256.0).ok();
+            }
+            if let Some(ref ly) = self.warning_layout {
+                let ih = self.input_layout.as_ref().unwrap().bounds().h.max(self.prompt_bounds().h);
+                rx.set_color(Color::rgb(0.9, 0.3, 0.3));
+                rx.draw_text_layout(self.input_origin() + Point::y(ih + 2.0), ly);
+                rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+            }
+        }
+        if self.output_stale {
+            self.output_stale = false;
+            self.output = match mathml::MathExpression::from_mathml(self.output_src.as_ref().unwrap().as_bytes(), rx, &fonts.math) {
+                Ok(o) => Some(o),
+                Err(e) => {
+                    println!("mathml error: {}", e);
+                    None
+                }
+            };
+        }
+        if let Some(ref o) = self.output {
+            let op = self.output_origin();
+            if let Some((_, r)) = self.output_selection.as_ref().and_then(|path| o.element(path)) {
+                rx.set_color(Color::rgba(0.4, 0.4, 0.6, 0.5));
+                rx.fill_rect(r.offset(op));
+                rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+            }
+            o.draw(op, rx);
+        }
+        let fp = self.plot_origin();
+        if let Some(ref mut f) = self.plot {
+            f.draw(fp, width, rx, fonts);
+        }
+        self.height = Some(self.bounds().h);
+    }
+
+    /// a dot between the label and the input showing how evaluation is going, and how long the last one took
+    fn draw_status(&mut self, rx: &mut RenderContext, fonts: &Fonts) {
+        let pb = self.prompt_bounds();
+        let dot = Rect::xywh(self.pos.x + pb.w + PROMPT_GAP/2.0 - 3.0, self.pos.y + pb.h/2.0 - 3.0, 6.0, 6.0);
+        let col = match self.state {
+            EvalState::Idle => None,
+            EvalState::Queued => Some(Color::rgb(0.45, 0.45, 0.5)),
+            EvalState::Running => Some(Color::rgb(0.9, 0.8, 0.3)),
+            EvalState::Done => Some(Color::rgb(0.4, 0.7, 0.4)),
+            EvalState::Failed => Some(Color::rgb(0.9, 0.3, 0.3))
+        };
+        if let Some(col) = col {
+            rx.set_color(col);
+            rx.fill_rect(dot);
+        }
+        if let Some(d) = self.elapsed {
+            if self.time_layout.is_none() {
+                self.time_layout = rx.new_text_layout(&format!("{:.3}s", duration_secs(d)), &fonts.text, 128.0, 64.0).ok();
+            }
+            if let Some(ref ly) = self.time_layout {
+                let tb = ly.bounds();
+                rx.set_color(Color::rgb(0.45, 0.45, 0.5));
+                // right-aligned in the space kept for it past the end of the input's lines
+                rx.draw_text_layout(self.input_origin() + Point::x(self.layout_width + (TIME_W - tb.w).max(8.0)), ly);
+            }
+        }
+        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+    }
+
+    /// a zero-width rectangle, relative to the input origin, where a caret at input position `i` goes
+    fn caret_rect(&self, i: usize) -> Rect {
+        let ly = self.input_layout.as_ref().unwrap();
+        if self.input.is_empty() {
+            return Rect::xywh(0.0, 0.0, 0.0, self.prompt_bounds().h);
+        }
+        if i < self.input.len() {
+            let b = ly.char_bounds(editor::utf16_offset(&self.input, i));
+            Rect::xywh(b.x, b.y, 0.0, b.h)
+        } else if self.input.ends_with('\n') {
+            // the caret is on the empty line after a trailing line break
+            let b = ly.char_bounds(editor::utf16_offset(&self.input, i) - 1);
+            Rect::xywh(0.0, b.y + b.h, 0.0, b.h)
+        } else {
+            let b = ly.char_bounds(editor::utf16_offset(&self.input, i) - 1);
+            Rect::xywh(b.x + b.w, b.y, 0.0, b.h)
+        }
+    }
+
+    /// the window point at the bottom of a caret at input position `i`
+    pub fn caret_point(&self, i: usize) -> Point {
+        let r = self.caret_rect(i).offset(self.input_origin());
+        Point::xy(r.x, r.y + r.h)
+    }
+
+    /// find the input position closest to the window point `p`, if it is over the input
+    pub fn hit_input(&self, p: Point) -> Option<usize> {
+        let lp = p - self.input_origin();
+        let ly = self.input_layout.as_ref()?;
+        if lp.y < 0.0 || lp.y > ly.bounds().h.max(self.prompt_bounds().h) { return None; }
+        if self.input.is_empty() || lp.x <= 0.0 { return Some(0); }
+        Some(match ly.hit_test(lp) {
+            Some((i, r)) => {
+                let i = editor::grapheme_start(&self.input, editor::byte_offset(&self.input, i));
+                if lp.x > r.x + r.w/2.0 { editor::next_grapheme(&self.input, i) } else { i }
+            },
+            None => self.input.len()
+        })
+    }
+
+    /// the input position one visual line above (`lines` < 0) or below the caret at `i`, keeping
+    /// the caret's horizontal position, or None if that would move past the first or last line
+    pub fn vertical_move(&self, i: usize, lines: f32) -> Option<usize> {
+        let r = self.caret_rect(i);
+        let target = Point::xy(r.x, r.y + r.h/2.0 + lines*r.h);
+        let h = self.input_layout.as_ref().map(|ly| ly.bounds().h).unwrap_or(0.0);
+        if target.y < 0.0 || target.y > h {
+            None
+        } else {
+            Some(self.hit_input(self.input_origin() + target).unwrap_or(i))
+        }
+    }
+
+    pub fn draw_cursor(&self, rx: &mut RenderContext, cursor: &Cursor) {
+        let o = self.input_origin();
+        if let Some(sel) = cursor.selection() {
+            // highlight character by character so that selections spanning lines come out right
+            let ly = self.input_layout.as_ref().unwrap();
+            rx.set_color(Color::rgba(0.4, 0.4, 0.6, 0.5));
+            for (i, _) in self.input[sel.clone()].char_indices() {
+                rx.fill_rect(ly.char_bounds(editor::utf16_offset(&self.input, sel.start + i)).offset(o));
+            }
+        }
+        if self.kind == CellKind::Code {
+            if let Some((a, b)) = syntax::matching_bracket(&self.input, cursor.pos) {
+                let ly = self.input_layout.as_ref().unwrap();
+                rx.set_color(Color::rgba(0.6, 0.6, 0.8, 0.7));
+                for &i in &[a, b] {
+                    rx.stroke_rect(ly.char_bounds(editor::utf16_offset(&self.input, i)).offset(o), 1.0);
+                }
+            }
+        }
+        let cb = self.caret_rect(cursor.pos).offset(o);
+        rx.set_color(Color::rgba(0.6, 0.6, 0.8, 0.9));
+        rx.draw_line(Point::xy(cb.x, cb.y), Point::xy(cb.x, cb.y+cb.h), 2.0);
+        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+    }
+}
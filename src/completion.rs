@@ -0,0 +1,130 @@
+use syntax::{self, TokenKind};
+
+/// Where a completion candidate comes from. Candidates are listed in this order
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum CandidateKind {
+    Function, Value, Option, Symbol
+}
+
+impl CandidateKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CandidateKind::Function => "function",
+            CandidateKind::Value => "value",
+            CandidateKind::Option => "option",
+            CandidateKind::Symbol => "symbol"
+        }
+    }
+}
+
+/// Names to complete from, as last reported by Maxima
+pub struct Completions {
+    symbols: Vec<(String, CandidateKind)>,
+    definitions: Vec<(String, CandidateKind)>
+}
+
+/// read `kind name` lines from a query reply, dropping names that can't be typed as identifiers
+fn parse_reply(reply: &str) -> Vec<(String, CandidateKind)> {
+    reply.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        let kind = match words.next() {
+            Some("f") => CandidateKind::Function,
+            Some("v") => CandidateKind::Value,
+            Some("o") => CandidateKind::Option,
+            Some("s") => CandidateKind::Symbol,
+            _ => return None
+        };
+        let name = words.next()?;
+        if name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '%') {
+            Some((String::from(name), kind))
+        } else {
+            None
+        }
+    }).collect()
+}
+
+/// names given definitions in some input, as with `f(x) := ...` or `a: 1`
+pub fn defined_names(input: &str) -> Vec<(String, CandidateKind)> {
+    let tokens = syntax::lex(input);
+    tokens.iter().enumerate().filter(|&(_, t)| t.kind == TokenKind::Definition).filter_map(|(i, t)| {
+        let name = &input[t.range.clone()];
+        if name.starts_with(':') { return None; }
+        let call = tokens.get(i+1).map_or(false, |n| &input[n.range.clone()] == "(");
+        Some((String::from(name), if call { CandidateKind::Function } else { CandidateKind::Value }))
+    }).collect()
+}
+
+impl Completions {
+    pub fn new() -> Completions {
+        Completions { symbols: Vec::new(), definitions: Vec::new() }
+    }
+
+    pub fn set_symbols(&mut self, reply: &str) {
+        self.symbols = parse_reply(reply);
+    }
+
+    pub fn set_definitions(&mut self, reply: &str) {
+        self.definitions = parse_reply(reply);
+    }
+
+    /// Every known name starting with `prefix`, best first. `notebook` holds names defined in the
+    /// notebook's cells, which count even if those cells haven't been evaluated yet
+    pub fn candidates(&self, prefix: &str, notebook: &[(String, CandidateKind)]) -> Vec<(String, CandidateKind)> {
+        let mut found: Vec<(String, CandidateKind)> = notebook.iter().chain(self.definitions.iter()).chain(self.symbols.iter())
+            .filter(|&&(ref name, _)| name.starts_with(prefix) && name != prefix)
+            .cloned().collect();
+        found.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.len().cmp(&b.0.len())).then_with(|| a.0.cmp(&b.0)));
+        let mut seen = ::std::collections::HashSet::new();
+        found.retain(|&(ref name, _)| seen.insert(name.clone()));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(found: &[(String, CandidateKind)]) -> Vec<&str> {
+        found.iter().map(|&(ref n, _)| n.as_str()).collect()
+    }
+
+    #[test]
+    fn replies() {
+        let found = parse_reply("f integrate\nv %pi\n\no ratprint\ns foo_bar\nx unknown\nf\nf a-b\n");
+        assert_eq!(found, vec![
+            (String::from("integrate"), CandidateKind::Function),
+            (String::from("%pi"), CandidateKind::Value),
+            (String::from("ratprint"), CandidateKind::Option),
+            (String::from("foo_bar"), CandidateKind::Symbol)
+        ]);
+    }
+
+    #[test]
+    fn definitions() {
+        let found = defined_names("f(x) := x^2;\na: 1$ g(x, y) := block([t: x], t + y);\n/* h(x) := 0 */ s: \"b: 2\";");
+        assert_eq!(found, vec![
+            (String::from("f"), CandidateKind::Function),
+            (String::from("a"), CandidateKind::Value),
+            (String::from("g"), CandidateKind::Function),
+            (String::from("t"), CandidateKind::Value),
+            (String::from("s"), CandidateKind::Value)
+        ]);
+    }
+
+    #[test]
+    fn filtering_and_order() {
+        let mut c = Completions::new();
+        c.set_symbols("f integrate\nf int\no intfaclim\nv integer\nf expand\n");
+        c.set_definitions("v interval\n");
+        let notebook = defined_names("intsum(x) := x;");
+        let found = c.candidates("int", &notebook);
+        // functions first, then values and options, shortest first within each; the prefix itself isn't offered
+        assert_eq!(names(&found), ["intsum", "integrate", "integer", "interval", "intfaclim"]);
+        assert_eq!(found[0].1, CandidateKind::Function);
+        // a name known twice is offered once, as its first kind
+        c.set_definitions("v integrate\n");
+        let found = c.candidates("integr", &[]);
+        assert_eq!(found, vec![(String::from("integrate"), CandidateKind::Function)]);
+        assert!(c.candidates("zzz", &notebook).is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use plot::{Plot2d, Curve};
+
+/// Lisp run at startup after `surface::SURFACE_LISP`, whose grid sampler it shares, which defines
+/// an `implicit_plot` and a `contour_plot` that print sampled grids between <contour></contour>.
+/// Inside are options as `name|value|...`, `kind|` with which of the two it is, `axes|` with the
+/// names of the variables, and then for each equation or function a `field|` line with its label
+/// followed by a grid as `plot3d` prints it. An equation is sampled as its left side minus its right
+pub const CONTOUR_LISP: &'static str = "\
+(defun rm-implicit-plot (eqs xr yr &rest options) \
+  (princ \"<contour>\") (terpri) \
+  (unwind-protect \
+    (progn \
+      (mapc #'rm-plot-option (remove-if-not #'$listp options)) \
+      (format t \"kind|implicit~%axes|~a|~a~%\" ($sconcat (cadr xr)) ($sconcat (cadr yr))) \
+      (dolist (e (if ($listp eqs) (cdr eqs) (list eqs))) \
+        (format t \"field|~a~%\" ($string e)) \
+        (rm-plot-grid (if (and (consp e) (eq (caar e) 'mequal)) (list '(mplus) (cadr e) (list '(mtimes) -1 (caddr e))) e) xr yr 80))) \
+    (princ \"</contour>\") (terpri)) \
+  '$done) \
+(defun rm-contour-plot (fun xr yr &rest options) \
+  (princ \"<contour>\") (terpri) \
+  (unwind-protect \
+    (progn \
+      (mapc #'rm-plot-option (remove-if-not #'$listp options)) \
+      (format t \"kind|contour~%axes|~a|~a~%field|~a~%\" ($sconcat (cadr xr)) ($sconcat (cadr yr)) ($string fun)) \
+      (rm-plot-grid fun xr yr 60)) \
+    (princ \"</contour>\") (terpri)) \
+  '$done)";
+
+/// A crossing point of a contour on the edge of a grid cell, named by the grid point at the edge's
+/// low end and whether the edge runs along x (0) or along y (1)
+type EdgeId = (usize, usize, u8);
+
+/// A function of two variables sampled over a grid, as for one equation of an implicit plot
+struct Field {
+    label: String,
+    z: Vec<Vec<Option<f32>>>
+}
+
+impl Field {
+    /// Trace where the field equals `level` by marching squares, giving each contour as a line
+    /// through the grid, in grid units
+    fn contours(&self, level: f32) -> Vec<Vec<(f32, f32)>> {
+        let rows = self.z.len();
+        let cols = if rows > 0 { self.z[0].len() } else { 0 };
+        let mut points: HashMap<EdgeId, (f32, f32)> = HashMap::new();
+        let mut segments: Vec<(EdgeId, EdgeId)> = Vec::new();
+        for j in 0..rows.saturating_sub(1) {
+            for i in 0..cols.saturating_sub(1) {
+                let v = match (self.z[j][i], self.z[j][i+1], self.z[j+1][i+1], self.z[j+1][i]) {
+                    (Some(a), Some(b), Some(c), Some(d)) => [a - level, b - level, c - level, d - level],
+                    _ => continue
+                };
+                // the cell's edges in order around it, each with its ends and the grid points there
+                let edges = [
+                    ((i, j, 0), 0, 1, (i as f32, j as f32), (i as f32 + 1.0, j as f32)),
+                    ((i+1, j, 1), 1, 2, (i as f32 + 1.0, j as f32), (i as f32 + 1.0, j as f32 + 1.0)),
+                    ((i, j+1, 0), 3, 2, (i as f32, j as f32 + 1.0), (i as f32 + 1.0, j as f32 + 1.0)),
+                    ((i, j, 1), 0, 3, (i as f32, j as f32), (i as f32, j as f32 + 1.0))
+                ];
+                let mut crossed = Vec::new();
+                for (k, &(id, a, b, pa, pb)) in edges.iter().enumerate() {
+                    if (v[a] < 0.0) != (v[b] < 0.0) {
+                        let t = v[a] / (v[a] - v[b]);
+                        points.insert(id, (pa.0 + (pb.0 - pa.0)*t, pa.1 + (pb.1 - pa.1)*t));
+                        crossed.push((k, id));
+                    }
+                }
+                match crossed.len() {
+                    2 => segments.push((crossed[0].1, crossed[1].1)),
+                    4 => {
+                        // a saddle: the middle of the cell decides which corners are joined
+                        let centre = (v[0] + v[1] + v[2] + v[3]) / 4.0;
+                        if (centre < 0.0) == (v[0] < 0.0) {
+                            segments.push((crossed[0].1, crossed[1].1));
+                            segments.push((crossed[2].1, crossed[3].1));
+                        } else {
+                            segments.push((crossed[0].1, crossed[3].1));
+                            segments.push((crossed[1].1, crossed[2].1));
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        // join the segments end to end, following each chain from wherever it starts
+        let mut ends: HashMap<EdgeId, Vec<usize>> = HashMap::new();
+        for (n, &(a, b)) in segments.iter().enumerate() {
+            ends.entry(a).or_insert_with(Vec::new).push(n);
+            ends.entry(b).or_insert_with(Vec::new).push(n);
+        }
+        let mut used = vec![false; segments.len()];
+        let mut lines = Vec::new();
+        // chains with a loose end are started from it, so that they aren't cut in two
+        let mut starts: Vec<usize> = (0..segments.len()).filter(|&n| {
+            let (a, b) = segments[n];
+            ends[&a].len() == 1 || ends[&b].len() == 1
+        }).collect();
+        starts.extend(0..segments.len());
+        for n in starts {
+            if used[n] { continue; }
+            used[n] = true;
+            let (a, b) = segments[n];
+            let (mut at, mut line) = if ends[&a].len() == 1 { (b, vec![a, b]) } else { (a, vec![b, a]) };
+            while let Some(&next) = ends[&at].iter().find(|&&m| !used[m]) {
+                used[next] = true;
+                let (c, d) = segments[next];
+                at = if c == at { d } else { c };
+                line.push(at);
+            }
+            lines.push(line.iter().map(|e| points[e]).collect());
+        }
+        lines
+    }
+}
+
+/// Read what `implicit_plot` or `contour_plot` from `CONTOUR_LISP` prints between
+/// <contour></contour>, tracing the curves each one asks for: where each equation holds for an
+/// implicit plot, or a curve for each of about ten evenly spaced levels for a contour plot
+pub fn parse(text: &str) -> Option<Plot2d> {
+    let mut contour = false;
+    let mut axes = (String::from("x"), String::from("y"));
+    let mut range = None;
+    let mut fields: Vec<Field> = Vec::new();
+    let mut options = String::new();
+    for line in text.lines().map(|l| l.trim()) {
+        if line.starts_with('r') && !line.contains('|') {
+            let row = line[1..].split_whitespace().map(|v| v.parse().ok().filter(|z: &f32| z.is_finite())).collect();
+            fields.last_mut()?.z.push(row);
+            continue;
+        }
+        let parts: Vec<&str> = line.split('|').collect();
+        let num = |i: usize| parts.get(i).and_then(|v| v.parse::<f32>().ok());
+        match parts[0] {
+            "kind" => contour = parts.get(1) == Some(&"contour"),
+            "axes" if parts.len() == 3 => axes = (String::from(parts[1]), String::from(parts[2])),
+            "field" => fields.push(Field { label: parts[1..].join("|"), z: Vec::new() }),
+            "grid" => range = Some(((num(2)?, num(3)?), (num(4)?, num(5)?))),
+            // everything else is a plot option, which the 2D plot reads
+            _ => { options.push_str(line); options.push('\n'); }
+        }
+    }
+    let ((x0, x1), (y0, y1)) = range?;
+    let mut plot = Plot2d::parse(&options);
+    plot.x_range = Some((x0, x1));
+    plot.y_range = Some((y0, y1));
+    plot.xlabel = plot.xlabel.or(Some(axes.0));
+    plot.ylabel = plot.ylabel.or(Some(axes.1));
+
+    for f in &fields {
+        let rows = f.z.len();
+        let cols = f.z.first().map_or(0, |r| r.len());
+        if rows < 2 || cols < 2 || f.z.iter().any(|r| r.len() != cols) { return None; }
+        // from grid units to the plot's own
+        let to_plot = |(i, j): (f32, f32)| (x0 + i / (cols - 1) as f32 * (x1 - x0), y0 + j / (rows - 1) as f32 * (y1 - y0));
+        let levels: Vec<(String, f32)> = if contour {
+            let zs = f.z.iter().flat_map(|r| r.iter()).filter_map(|&z| z);
+            let (lo, hi) = zs.fold((::std::f32::INFINITY, ::std::f32::NEG_INFINITY), |(lo, hi), z| (lo.min(z), hi.max(z)));
+            if !(hi > lo) { continue; }
+            (1..10).map(|k| lo + (hi - lo) * k as f32 / 10.0).map(|l| (format!("{} = {}", f.label, (l * 1000.0).round() / 1000.0), l)).collect()
+        } else {
+            vec![(f.label.clone(), 0.0)]
+        };
+        for (label, level) in levels {
+            let pieces = f.contours(level).into_iter().map(|l| l.into_iter().map(&to_plot).collect()).collect();
+            plot.curves.push(Curve { label, pieces });
+        }
+    }
+    Some(plot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Field};
+
+    fn field(rows: &[&[f32]]) -> Field {
+        Field { label: String::from("f"), z: rows.iter().map(|r| r.iter().map(|&z| Some(z)).collect()).collect() }
+    }
+
+    fn near(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    /// the lines as sets of points, each line and the whole set in order, for comparing
+    fn sorted(mut lines: Vec<Vec<(f32, f32)>>) -> Vec<Vec<(f32, f32)>> {
+        let cmp = |a: &(f32, f32), b: &(f32, f32)| a.partial_cmp(b).unwrap();
+        for l in &mut lines { l.sort_by(&cmp); }
+        lines.sort_by(|a, b| cmp(&a[0], &b[0]));
+        lines
+    }
+
+    #[test]
+    fn saddle() {
+        // the middle of the cell is above the level, so the two high corners are joined through it
+        let lines = sorted(field(&[&[1.0, -1.0], &[-1.0, 2.0]]).contours(0.0));
+        assert_eq!(lines.len(), 2);
+        assert!(near(lines[0][0], (0.0, 0.5)) && near(lines[0][1], (1.0 / 3.0, 1.0)));
+        assert!(near(lines[1][0], (0.5, 0.0)) && near(lines[1][1], (1.0, 1.0 / 3.0)));
+        // and here it is below, so the low corners are
+        let lines = sorted(field(&[&[1.0, -2.0], &[-1.0, 1.0]]).contours(0.0));
+        assert_eq!(lines.len(), 2);
+        assert!(near(lines[0][0], (0.0, 0.5)) && near(lines[0][1], (1.0 / 3.0, 0.0)));
+        assert!(near(lines[1][0], (0.5, 1.0)) && near(lines[1][1], (1.0, 2.0 / 3.0)));
+    }
+
+    #[test]
+    fn closed_loop() {
+        let lines = field(&[&[0.0, 0.0, 0.0], &[0.0, 1.0, 0.0], &[0.0, 0.0, 0.0]]).contours(0.5);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), 5);
+        assert!(near(lines[0][0], lines[0][4]));
+    }
+
+    #[test]
+    fn loose_ends() {
+        // a bump against the bottom edge, whose contour is first met in its middle, is still one line
+        let lines = field(&[&[0.0, 0.0, 0.0, 0.0], &[0.0, 1.0, 1.0, 0.0], &[0.0, 1.0, 1.0, 0.0]]).contours(0.5);
+        assert_eq!(lines.len(), 1);
+        let l = &lines[0];
+        assert_eq!(l.len(), 6);
+        assert!(l[0].1 == 2.0 && l[5].1 == 2.0 && !near(l[0], l[5]));
+    }
+
+    #[test]
+    fn gaps() {
+        // no line goes through a cell with a corner where the function isn't real
+        let mut f = field(&[&[0.0, 0.0, 0.0], &[0.0, 1.0, 0.0], &[0.0, 0.0, 0.0]]);
+        f.z[0][0] = None;
+        let lines = f.contours(0.5);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), 4);
+        assert!(!near(lines[0][0], lines[0][3]));
+    }
+
+    const GRID: &'static str = "grid|2|-1.0|1.0|-1.0|1.0\nr 1.0 0.5 1.0\nr 0.5 -1.0 0.5\nr 1.0 0.5 1.0\n";
+
+    #[test]
+    fn implicit_plot() {
+        let text = format!("title|circle\nkind|implicit\naxes|u|v\nfield|u^2+v^2 = 1\n{}", GRID);
+        let plot = parse(&text).unwrap();
+        assert_eq!(plot.title, Some(String::from("circle")));
+        assert_eq!((plot.xlabel, plot.ylabel), (Some(String::from("u")), Some(String::from("v"))));
+        assert_eq!(plot.x_range, Some((-1.0, 1.0)));
+        assert_eq!(plot.curves.len(), 1);
+        assert_eq!(plot.curves[0].label, "u^2+v^2 = 1");
+        // one loop around the middle, in the plot's units
+        let pieces = &plot.curves[0].pieces;
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].len(), 5);
+        assert!(pieces[0].iter().any(|&p| near(p, (-2.0 / 3.0, 0.0))));
+    }
+
+    #[test]
+    fn contour_plot() {
+        let plot = parse(&format!("kind|contour\naxes|x|y\nfield|f\n{}", GRID)).unwrap();
+        assert_eq!(plot.curves.len(), 9);
+        assert_eq!(plot.curves[0].label, "f = -0.8");
+        assert_eq!(plot.curves[8].label, "f = 0.8");
+        // rows of different lengths, or no grid at all, aren't a plot
+        assert!(parse("kind|contour\nfield|f\ngrid|2|0|1|0|1\nr 1 2 3\nr 1 2\n").is_none());
+        assert!(parse("kind|contour\nfield|f\n").is_none());
+    }
+}
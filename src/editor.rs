@@ -0,0 +1,290 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use syntax::{self, TokenKind};
+
+/// text layouts index by UTF-16 code unit, so convert a byte offset into `text` for them
+pub fn utf16_offset(text: &str, byte: usize) -> usize {
+    text[..byte].encode_utf16().count()
+}
+
+/// convert a UTF-16 offset from a text layout back into a byte offset into `text`
+pub fn byte_offset(text: &str, utf16: usize) -> usize {
+    let mut n = 0;
+    for (i, c) in text.char_indices() {
+        if n >= utf16 { return i; }
+        n += c.len_utf16();
+    }
+    text.len()
+}
+
+/// the grapheme boundary at or before `pos`
+pub fn grapheme_start(text: &str, pos: usize) -> usize {
+    text.grapheme_indices(true).map(|(i, _)| i).take_while(|&i| i <= pos).last().unwrap_or(0)
+}
+
+pub fn prev_grapheme(text: &str, pos: usize) -> usize {
+    text[..pos].grapheme_indices(true).next_back().map(|(i, _)| i).unwrap_or(0)
+}
+
+pub fn next_grapheme(text: &str, pos: usize) -> usize {
+    text[pos..].graphemes(true).next().map(|g| pos + g.len()).unwrap_or(pos)
+}
+
+pub fn line_start(text: &str, pos: usize) -> usize {
+    text[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+pub fn line_end(text: &str, pos: usize) -> usize {
+    text[pos..].find('\n').map(|i| pos + i).unwrap_or(text.len())
+}
+
+const INDENT: &'static str = "    ";
+
+/// Caret position and selection anchor within a cell's input, as byte offsets into the input text.
+/// Both always sit on grapheme cluster boundaries
+pub struct Cursor {
+    pub pos: usize,
+    pub anchor: Option<usize>
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '%'
+}
+
+/// the start of the word that the caret at `pos` is in or just after
+pub fn word_start(text: &str, pos: usize) -> usize {
+    text[..pos].char_indices().rev()
+        .take_while(|&(_, c)| is_word_char(c)).last().map(|(i, _)| i).unwrap_or(pos)
+}
+
+/// the end of the word that the caret at `pos` is in or just before
+pub fn word_end(text: &str, pos: usize) -> usize {
+    text[pos..].char_indices()
+        .find(|&(_, c)| !is_word_char(c)).map(|(i, _)| pos + i).unwrap_or(text.len())
+}
+
+impl Cursor {
+    pub fn new() -> Cursor {
+        Cursor { pos: 0, anchor: None }
+    }
+
+    /// the selected range, if the anchor and caret are apart
+    pub fn selection(&self) -> Option<Range<usize>> {
+        self.anchor.and_then(|a| {
+            if a == self.pos { None } else { Some(a.min(self.pos)..a.max(self.pos)) }
+        })
+    }
+
+    /// move the caret, either dragging the selection along with it or dropping it
+    pub fn move_to(&mut self, pos: usize, extend: bool) {
+        if extend {
+            if self.anchor.is_none() { self.anchor = Some(self.pos); }
+        } else {
+            self.anchor = None;
+        }
+        self.pos = pos;
+    }
+
+    pub fn left(&mut self, text: &str, extend: bool) {
+        let p = prev_grapheme(text, self.pos);
+        match self.selection() {
+            Some(ref r) if !extend => self.move_to(r.start, false),
+            _ => self.move_to(p, extend)
+        }
+    }
+
+    pub fn right(&mut self, text: &str, extend: bool) {
+        let p = next_grapheme(text, self.pos);
+        match self.selection() {
+            Some(ref r) if !extend => self.move_to(r.end, false),
+            _ => self.move_to(p, extend)
+        }
+    }
+
+    pub fn home(&mut self, text: &str, extend: bool) {
+        let p = line_start(text, self.pos);
+        self.move_to(p, extend);
+    }
+
+    pub fn end(&mut self, text: &str, extend: bool) {
+        let p = line_end(text, self.pos);
+        self.move_to(p, extend);
+    }
+
+    pub fn select_all(&mut self, text: &str) {
+        self.anchor = Some(0);
+        self.pos = text.len();
+    }
+
+    /// select the word surrounding the caret
+    pub fn select_word(&mut self, text: &str) {
+        let start = word_start(text, self.pos);
+        let end = word_end(text, self.pos);
+        self.anchor = Some(start);
+        self.pos = end;
+    }
+
+    pub fn selected_text<'a>(&self, text: &'a str) -> Option<&'a str> {
+        self.selection().map(|r| &text[r])
+    }
+
+    /// remove the selected text, returning true if there was anything to remove
+    pub fn delete_selection(&mut self, text: &mut String) -> bool {
+        match self.selection() {
+            Some(r) => {
+                text.drain(r.clone());
+                self.move_to(r.start, false);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// insert at the caret, replacing the selection if there is one
+    pub fn insert(&mut self, text: &mut String, s: &str) {
+        self.delete_selection(text);
+        text.insert_str(self.pos, s);
+        let p = self.pos + s.len();
+        self.move_to(p, false);
+    }
+
+    /// break the line at the caret, indenting the new line one level per open bracket.
+    /// If the caret is right before a closing bracket, that bracket goes on its own line as well
+    pub fn newline(&mut self, text: &mut String) {
+        self.delete_selection(text);
+        // brackets in strings and comments don't count
+        let depth = syntax::lex(&text[..self.pos]).iter().filter(|t| t.kind == TokenKind::Bracket)
+            .fold(0usize, |d, t| if "([{".contains(&text[t.range.clone()]) { d + 1 } else { d.saturating_sub(1) });
+        let closing = text[self.pos..].chars().next().map(|c| c == ')' || c == ']' || c == '}').unwrap_or(false);
+        let mut s = String::from("\n");
+        s.push_str(&INDENT.repeat(depth));
+        self.insert(text, &s);
+        if closing && depth > 0 {
+            let p = self.pos;
+            text.insert_str(p, &format!("\n{}", INDENT.repeat(depth - 1)));
+        }
+    }
+
+    /// Format the selection in a text cell by putting `marker` on both sides of it, or take the
+    /// markers away if they are already there. With nothing selected the caret goes between a
+    /// new pair. Stars are counted so that italic can go inside or outside of bold
+    pub fn toggle_marker(&mut self, text: &mut String, marker: &str) {
+        let sel = self.selection().unwrap_or(self.pos..self.pos);
+        let m = marker.len();
+        let c = marker.chars().next().unwrap();
+        let before = text[..sel.start].chars().rev().take_while(|&x| x == c).count();
+        let after = text[sel.end..].chars().take_while(|&x| x == c).count();
+        let run = before.min(after);
+        let present = if marker == "*" { run % 2 == 1 } else { run >= m };
+        let (start, end) = if present {
+            text.drain(sel.end..sel.end + m);
+            text.drain(sel.start - m..sel.start);
+            (sel.start - m, sel.end - m)
+        } else {
+            text.insert_str(sel.end, marker);
+            text.insert_str(sel.start, marker);
+            (sel.start + m, sel.end + m)
+        };
+        if start == end {
+            self.move_to(start, false);
+        } else {
+            self.anchor = Some(start);
+            self.pos = end;
+        }
+    }
+
+    /// Type a character into code, pairing brackets and quotes: an opening one gets its closer
+    /// after the caret (or around the selection), and typing a closer that is already next just
+    /// steps over it
+    pub fn type_char(&mut self, text: &mut String, c: char) {
+        let next = text[self.pos..].chars().next();
+        let prev = text[..self.pos].chars().next_back();
+        let close = match c {
+            '(' => Some(')'), '[' => Some(']'), '{' => Some('}'), '"' => Some('"'),
+            _ => None
+        };
+        if self.selection().is_none() && (c == ')' || c == ']' || c == '}' || c == '"') && next == Some(c) {
+            let p = self.pos + c.len_utf8();
+            self.move_to(p, false);
+            return;
+        }
+        if let Some(close) = close {
+            if let Some(r) = self.selection() {
+                text.insert(r.end, close);
+                text.insert(r.start, c);
+                self.anchor = Some(r.start + 1);
+                self.pos = r.end + 1;
+                return;
+            }
+            // only pair up where the closer can't end up in front of something it shouldn't
+            let free = next.map_or(true, |n| n.is_whitespace() || ")]},;$".contains(n));
+            let after_word = c == '"' && prev.map_or(false, |p| p.is_alphanumeric() || p == '\\');
+            if free && !after_word {
+                text.insert(self.pos, close);
+                text.insert(self.pos, c);
+                let p = self.pos + c.len_utf8();
+                self.move_to(p, false);
+                return;
+            }
+        }
+        let mut buf = [0; 4];
+        self.insert(text, c.encode_utf8(&mut buf));
+    }
+
+    /// delete an empty bracket or quote pair around the caret, returning false if there isn't one
+    pub fn backspace_pair(&mut self, text: &mut String) -> bool {
+        if self.selection().is_some() || self.pos == 0 { return false; }
+        let pair = (text[..self.pos].chars().next_back(), text[self.pos..].chars().next());
+        match pair {
+            (Some('('), Some(')')) | (Some('['), Some(']')) | (Some('{'), Some('}')) | (Some('"'), Some('"')) => {
+                text.drain(self.pos-1..self.pos+1);
+                self.pos -= 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn backspace(&mut self, text: &mut String) {
+        if !self.delete_selection(text) && self.pos > 0 {
+            let p = prev_grapheme(text, self.pos);
+            text.drain(p..self.pos);
+            self.pos = p;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+
+    /// toggle `marker` on `text` with `sel` selected, giving the text and what is selected after
+    fn toggle(text: &str, sel: (usize, usize), marker: &str) -> (String, String) {
+        let mut text = String::from(text);
+        let mut c = Cursor::new();
+        c.move_to(sel.0, false);
+        c.move_to(sel.1, true);
+        c.toggle_marker(&mut text, marker);
+        let r = c.selection().unwrap_or(c.pos..c.pos);
+        let selected = String::from(&text[r]);
+        (text, selected)
+    }
+
+    #[test]
+    fn formatting_toggles() {
+        assert_eq!(toggle("a word", (2, 6), "**"), (String::from("a **word**"), String::from("word")));
+        assert_eq!(toggle("a **word**", (4, 8), "**"), (String::from("a word"), String::from("word")));
+        // italic goes inside bold, and comes back out without touching it
+        assert_eq!(toggle("**word**", (2, 6), "*"), (String::from("***word***"), String::from("word")));
+        assert_eq!(toggle("***word***", (3, 7), "*"), (String::from("**word**"), String::from("word")));
+        assert_eq!(toggle("***word***", (3, 7), "**"), (String::from("*word*"), String::from("word")));
+        // with nothing selected the caret goes between the markers
+        let mut text = String::from("ab");
+        let mut c = Cursor::new();
+        c.move_to(1, false);
+        c.toggle_marker(&mut text, "`");
+        assert_eq!((text.as_str(), c.pos), ("a``b", 2));
+    }
+}
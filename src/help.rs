@@ -0,0 +1,221 @@
+use runic::*;
+
+use cell::Fonts;
+
+/// A piece of a documentation entry's body
+pub enum Block {
+    Heading(String),
+    Text(String),
+    /// preformatted lines, such as an example session with 2D output
+    Example(String)
+}
+
+/// One documentation entry, as printed by `describe`
+pub struct Entry {
+    /// what the entry is for, like `Function: integrate`
+    pub title: String,
+    /// the ways of calling a function, as listed under the title
+    pub usages: Vec<String>,
+    pub blocks: Vec<Block>
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_left().len()
+}
+
+/// Break `describe` output into entries. The text comes from the info manual, so paragraphs are
+/// separated by blank lines, body text is indented a few spaces, and examples are indented further
+pub fn parse(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut after_title = false;
+    for chunk in text.split("\n\n").map(|c| c.trim_matches('\n')).filter(|c| !c.trim().is_empty()) {
+        let lines: Vec<&str> = chunk.lines().filter(|l| !l.trim().is_empty()).collect();
+        let first = lines[0].trim();
+        if first.starts_with("-- ") && first.contains(':') {
+            // a title, possibly with the usages right after it
+            entries.push(Entry { title: String::from(first[3..].trim()), usages: Vec::new(), blocks: Vec::new() });
+            let usages = lines[1..].iter().map(|l| String::from(l.trim()));
+            entries.last_mut().unwrap().usages.extend(usages);
+            after_title = true;
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some(e) => e,
+            None => continue
+        };
+        let min_indent = lines.iter().map(|l| indent(l)).min().unwrap_or(0);
+        if min_indent < 4 {
+            // the notes describe adds about other matches aren't part of the entry
+            continue;
+        }
+        if after_title && entry.usages.is_empty() && min_indent >= 9 {
+            entry.usages.extend(lines.iter().map(|l| String::from(l.trim())));
+            continue;
+        }
+        after_title = false;
+        let session = lines.iter().any(|l| l.contains("(%i") || l.contains("(%o"));
+        if min_indent >= 9 && (session || !first.starts_with("* ")) {
+            let ex: Vec<&str> = lines.iter().map(|l| &l[min_indent..]).collect();
+            entry.blocks.push(Block::Example(ex.join("\n")));
+        } else if lines.len() == 1 && first.ends_with(':') && first.len() < 40 {
+            entry.blocks.push(Block::Heading(String::from(first.trim_right_matches(':'))));
+        } else {
+            let words: Vec<&str> = lines.iter().map(|l| l.trim()).collect();
+            entry.blocks.push(Block::Text(words.join(" ")));
+        }
+    }
+    entries
+}
+
+/// A side panel showing the documentation for one topic
+pub struct HelpPanel {
+    pub topic: String,
+    entries: Vec<Entry>,
+    /// each piece of text laid out, with its color and the space left after it
+    layouts: Option<Vec<(TextLayout, Color, f32)>>,
+    layout_width: f32,
+    scroll: f32,
+    height: f32
+}
+
+impl HelpPanel {
+    pub fn new(topic: String, entries: Vec<Entry>) -> HelpPanel {
+        HelpPanel { topic, entries, layouts: None, layout_width: 0.0, scroll: 0.0, height: 0.0 }
+    }
+
+    /// where the panel goes in a window of size `bnds`
+    pub fn rect(&self, bnds: Rect) -> Rect {
+        let w = (bnds.w * 0.45).max(320.0).min(bnds.w);
+        Rect::xywh(bnds.w - w, 0.0, w, bnds.h)
+    }
+
+    pub fn scroll_by(&mut self, dy: f32, view_h: f32) {
+        self.scroll = (self.scroll + dy).max(0.0).min((self.height - view_h + 16.0).max(0.0));
+    }
+
+    fn lay_out(&mut self, rx: &mut RenderContext, fonts: &Fonts, width: f32) {
+        let text_col = Color::rgb(0.8, 0.75, 0.7);
+        let mut ly = Vec::new();
+        {
+            let mut add = |s: &str, font: &Font, col: Color, gap: f32| {
+                ly.push((rx.new_text_layout(s, font, width, 8192.0).expect("create text layout"), col, gap));
+            };
+            if self.entries.is_empty() {
+                add(&format!("No documentation found for `{}`", self.topic), &fonts.text, text_col, 0.0);
+            }
+            for e in &self.entries {
+                add(&e.title, &fonts.subsection, Color::rgb(0.9, 0.8, 0.45), 4.0);
+                for u in &e.usages {
+                    add(u, &fonts.code, Color::rgb(0.6, 0.75, 0.9), 2.0);
+                }
+                for b in &e.blocks {
+                    match *b {
+                        Block::Heading(ref s) => add(s, &fonts.text, Color::rgb(0.75, 0.55, 0.85), 4.0),
+                        Block::Text(ref s) => add(s, &fonts.text, text_col, 8.0),
+                        Block::Example(ref s) => add(s, &fonts.code, Color::rgb(0.65, 0.7, 0.65), 8.0)
+                    }
+                }
+            }
+        }
+        self.layouts = Some(ly);
+        self.layout_width = width;
+    }
+
+    pub fn draw(&mut self, rx: &mut RenderContext, fonts: &Fonts) {
+        let panel = self.rect(rx.bounds());
+        if self.layouts.is_none() || self.layout_width != panel.w - 24.0 {
+            self.lay_out(rx, fonts, panel.w - 24.0);
+        }
+        rx.set_color(Color::rgba(0.1, 0.1, 0.12, 0.95));
+        rx.fill_rect(panel);
+        let mut y = 8.0;
+        for &(ref ly, col, gap) in self.layouts.as_ref().unwrap() {
+            let h = ly.bounds().h;
+            if y + h > self.scroll && y < self.scroll + panel.h {
+                rx.set_color(col);
+                rx.draw_text_layout(Point::xy(panel.x + 12.0, y - self.scroll), ly);
+            }
+            y += h + gap + 4.0;
+        }
+        self.height = y;
+        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Block};
+
+    const INTEGRATE: &'static str = concat!(
+        " -- Function: integrate\n",
+        "          integrate (<expr>, <x>)\n",
+        "          integrate (<expr>, <x>, <a>, <b>)\n",
+        "\n",
+        "     Attempts to symbolically compute the integral of <expr> with\n",
+        "     respect to <x>.\n",
+        "\n",
+        "     Examples:\n",
+        "\n",
+        "          (%i1) integrate (x^2, x);\n",
+        "                                  3\n",
+        "                                 x\n",
+        "          (%o1)                  --\n",
+        "                                 3\n",
+        "\n",
+        "  There are also some inexact matches for `integrate'.\n",
+        "  Try `?? integrate' to see them.\n");
+
+    fn text(b: &Block) -> (&'static str, &str) {
+        match *b {
+            Block::Heading(ref s) => ("heading", s),
+            Block::Text(ref s) => ("text", s),
+            Block::Example(ref s) => ("example", s)
+        }
+    }
+
+    #[test]
+    fn single_match() {
+        let entries = parse(INTEGRATE);
+        assert_eq!(entries.len(), 1);
+        let e = &entries[0];
+        assert_eq!(e.title, "Function: integrate");
+        assert_eq!(e.usages, ["integrate (<expr>, <x>)", "integrate (<expr>, <x>, <a>, <b>)"]);
+        let blocks: Vec<(&str, &str)> = e.blocks.iter().map(text).collect();
+        // the note about inexact matches is left out
+        assert_eq!(blocks, vec![
+            ("text", "Attempts to symbolically compute the integral of <expr> with respect to <x>."),
+            ("heading", "Examples"),
+            ("example", "(%i1) integrate (x^2, x);\n                        3\n                       x\n(%o1)                  --\n                       3")
+        ]);
+    }
+
+    #[test]
+    fn several_matches() {
+        let reply = concat!(
+            " -- Function: expand (<expr>)\n",
+            "\n",
+            "     Expand products and powers.\n",
+            "\n",
+            " -- Option variable: expandwrt_denom\n",
+            "\n",
+            "          Default value: 'false'\n",
+            "\n",
+            "     Controls the treatment of rational expressions by 'expandwrt'.\n");
+        let entries = parse(reply);
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["Function: expand (<expr>)", "Option variable: expandwrt_denom"]);
+        assert!(entries[0].usages.is_empty());
+        assert_eq!(entries[0].blocks.iter().map(text).collect::<Vec<_>>(), vec![("text", "Expand products and powers.")]);
+        // usages can come in a paragraph of their own after the title
+        assert_eq!(entries[1].usages, ["Default value: 'false'"]);
+        assert_eq!(entries[1].blocks.len(), 1);
+    }
+
+    #[test]
+    fn no_match() {
+        assert!(parse("").is_empty());
+        assert!(parse("\n\n").is_empty());
+        // what describe prints when there is only an inexact match
+        assert!(parse("  No exact match found for topic `integr'.\n  Try `?? integr' (inexact match) instead.\n\n").is_empty());
+    }
+}
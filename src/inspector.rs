@@ -0,0 +1,131 @@
+use runic::*;
+
+use mathml::MathExpression;
+use cell::Fonts;
+
+/// What a name is bound to in the Maxima session
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BindingKind {
+    Value, Function, Array, Dependency
+}
+
+impl BindingKind {
+    /// the marker `Query::Inspect` puts on each item
+    pub fn from_tag(tag: &str) -> Option<BindingKind> {
+        match tag {
+            "v" => Some(BindingKind::Value),
+            "f" => Some(BindingKind::Function),
+            "a" => Some(BindingKind::Array),
+            "d" => Some(BindingKind::Dependency),
+            _ => None
+        }
+    }
+
+    fn heading(&self) -> &'static str {
+        match *self {
+            BindingKind::Value => "Values",
+            BindingKind::Function => "Functions",
+            BindingKind::Array => "Arrays",
+            BindingKind::Dependency => "Dependencies"
+        }
+    }
+}
+
+pub struct Binding {
+    pub kind: BindingKind,
+    pub name: String,
+    /// MathML for the value, or the definition for a function, parsed when first drawn
+    src: String,
+    value: Option<MathExpression>,
+    name_layout: Option<TextLayout>
+}
+
+impl Binding {
+    pub fn new(kind: BindingKind, name: String, src: String) -> Binding {
+        Binding { kind, name, src, value: None, name_layout: None }
+    }
+}
+
+/// A side panel listing everything bound in the Maxima session, by kind
+pub struct Inspector {
+    bindings: Vec<Binding>,
+    headings: Vec<(BindingKind, TextLayout)>,
+    /// where each binding was drawn, for clicking
+    rects: Vec<(Rect, usize)>,
+    scroll: f32,
+    height: f32
+}
+
+impl Inspector {
+    pub fn new() -> Inspector {
+        Inspector { bindings: Vec::new(), headings: Vec::new(), rects: Vec::new(), scroll: 0.0, height: 0.0 }
+    }
+
+    pub fn set(&mut self, mut bindings: Vec<Binding>) {
+        bindings.sort_by_key(|b| b.kind as usize);
+        self.bindings = bindings;
+        self.rects.clear();
+    }
+
+    pub fn rect(&self, bnds: Rect) -> Rect {
+        let w = 320f32.min(bnds.w);
+        Rect::xywh(bnds.w - w, 0.0, w, bnds.h)
+    }
+
+    pub fn scroll_by(&mut self, dy: f32, view_h: f32) {
+        self.scroll = (self.scroll + dy).max(0.0).min((self.height - view_h + 16.0).max(0.0));
+    }
+
+    /// the name of the binding under `p`, to insert into the current cell
+    pub fn hit(&self, p: Point) -> Option<&str> {
+        self.rects.iter().find(|&&(r, _)| r.contains(p)).map(|&(_, i)| self.bindings[i].name.as_str())
+    }
+
+    pub fn draw(&mut self, rx: &mut RenderContext, fonts: &Fonts) {
+        let panel = self.rect(rx.bounds());
+        rx.set_color(Color::rgba(0.1, 0.1, 0.12, 0.95));
+        rx.fill_rect(panel);
+        self.rects.clear();
+        let mut y = 8.0 - self.scroll;
+        let mut last_kind = None;
+        for (i, b) in self.bindings.iter_mut().enumerate() {
+            if last_kind != Some(b.kind) {
+                last_kind = Some(b.kind);
+                if !self.headings.iter().any(|&(k, _)| k == b.kind) {
+                    let ly = rx.new_text_layout(b.kind.heading(), &fonts.subsection, panel.w - 16.0, 64.0).expect("create text layout");
+                    self.headings.push((b.kind, ly));
+                }
+                let ly = &self.headings.iter().find(|&&(k, _)| k == b.kind).unwrap().1;
+                rx.set_color(Color::rgb(0.9, 0.8, 0.45));
+                rx.draw_text_layout(Point::xy(panel.x + 8.0, y), ly);
+                y += ly.bounds().h + 4.0;
+            }
+            if b.name_layout.is_none() {
+                b.name_layout = rx.new_text_layout(&b.name, &fonts.code, panel.w - 24.0, 64.0).ok();
+                b.value = match MathExpression::from_mathml(b.src.as_bytes(), rx, &fonts.math) {
+                    Ok(e) => Some(e),
+                    Err(e) => {
+                        println!("mathml error in {}: {}", b.name, e);
+                        None
+                    }
+                };
+            }
+            let top = y;
+            if let Some(ref ly) = b.name_layout {
+                rx.set_color(Color::rgb(0.6, 0.75, 0.9));
+                rx.draw_text_layout(Point::xy(panel.x + 16.0, y), ly);
+                y += ly.bounds().h + 2.0;
+            }
+            if let Some(ref v) = b.value {
+                let vb = v.bounds();
+                rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+                v.draw(Point::xy(panel.x + 24.0, y + vb.h/2.0), rx);
+                y += vb.h + 2.0;
+            }
+            self.rects.push((Rect::xywh(panel.x, top, panel.w, y - top), i));
+            y += 6.0;
+        }
+        self.height = y + self.scroll;
+        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+    }
+}
@@ -0,0 +1,271 @@
+use mathml::{Element, Leaf, Notation};
+
+/// Convert a parsed MathML expression into LaTeX source suitable for pasting into a document
+pub fn from_element(e: &Element) -> String {
+    let mut out = String::new();
+    // every construct has a LaTeX form
+    let _ = e.write(&Latex, &mut out);
+    out.trim().to_owned()
+}
+
+fn symbol(c: char) -> Option<&'static str> {
+    Some(match c {
+        'α' | '𝛼' => "\\alpha", 'β' | '𝛽' => "\\beta", 'γ' | '𝛾' => "\\gamma", 'δ' | '𝛿' => "\\delta",
+        'ε' | '𝜀' => "\\epsilon", 'ζ' | '𝜁' => "\\zeta", 'η' | '𝜂' => "\\eta", 'θ' | '𝜃' => "\\theta",
+        'ι' | '𝜄' => "\\iota", 'κ' | '𝜅' => "\\kappa", 'λ' | '𝜆' => "\\lambda", 'μ' | '𝜇' => "\\mu",
+        'ν' | '𝜈' => "\\nu", 'ξ' | '𝜉' => "\\xi", 'π' | '𝜋' => "\\pi", 'ρ' | '𝜌' => "\\rho",
+        'σ' | '𝜎' => "\\sigma", 'τ' | '𝜏' => "\\tau", 'υ' | '𝜐' => "\\upsilon", 'φ' | '𝜑' => "\\phi",
+        'χ' | '𝜒' => "\\chi", 'ψ' | '𝜓' => "\\psi", 'ω' | '𝜔' => "\\omega",
+        'Γ' => "\\Gamma", 'Δ' => "\\Delta", 'Θ' => "\\Theta", 'Λ' => "\\Lambda", 'Ξ' => "\\Xi",
+        'Π' => "\\Pi", 'Σ' => "\\Sigma", 'Φ' => "\\Phi", 'Ψ' => "\\Psi", 'Ω' => "\\Omega",
+        '𝒆' => "e", '𝑖' => "i", '𝑑' => "d",
+        '∫' => "\\int", '∑' => "\\sum", '∏' => "\\prod", '∞' => "\\infty",
+        '·' | '⋅' => "\\cdot", '×' => "\\times", '−' => "-", '±' => "\\pm", '∓' => "\\mp",
+        '≤' => "\\le", '≥' => "\\ge", '≠' => "\\ne", '≈' => "\\approx", '→' => "\\to",
+        '∂' => "\\partial", '∇' => "\\nabla", '′' => "'",
+        '\u{2062}' | '\u{2061}' => "",
+        _ => return None
+    })
+}
+
+const FUNCTIONS: &'static [&'static str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "asin", "acos", "atan",
+    "sinh", "cosh", "tanh", "coth", "log", "ln", "exp", "lim", "max", "min", "det", "gcd"
+];
+
+/// does the output end in a control word like `\pi` that would swallow a following letter?
+fn ends_with_command(out: &str) -> bool {
+    match out.rfind('\\') {
+        Some(i) => out.len() > i+1 && out[i+1..].chars().all(|c| c.is_ascii_alphabetic()),
+        None => false
+    }
+}
+
+fn push_token(out: &mut String, tok: &str) {
+    if tok.chars().next().map(|c| c.is_ascii_alphabetic()).unwrap_or(false) && ends_with_command(out) {
+        out.push(' ');
+    }
+    out.push_str(tok);
+}
+
+fn write_text(s: &str, out: &mut String) {
+    if s.chars().count() > 1 && s.chars().all(|c| c.is_ascii_alphabetic()) {
+        if FUNCTIONS.contains(&s) {
+            push_token(out, &format!("\\{}", s));
+        } else {
+            push_token(out, &format!("\\mathrm{{{}}}", s));
+        }
+        return;
+    }
+    for c in s.chars() {
+        match symbol(c) {
+            Some(t) => push_token(out, t),
+            None => match c {
+                '{' | '}' | '%' | '#' | '&' | '$' | '_' => { out.push('\\'); out.push(c); }
+                _ => { let mut b = [0; 4]; push_token(out, c.encode_utf8(&mut b)); }
+            }
+        }
+    }
+}
+
+/// leaves can be scripted directly, anything else needs braces around it
+fn write_base(e: &Element, out: &mut String) -> Option<()> {
+    match e {
+        &Element::Id(..) | &Element::Number(..) | &Element::Operator(..) => e.write(&Latex, out),
+        _ => Latex.group(e, out)
+    }
+}
+
+fn is_large_operator(e: &Element) -> bool {
+    match e {
+        &Element::Operator(ref s, ..) | &Element::Id(ref s, ..) =>
+            s == "∑" || s == "∏" || s == "∫" || s == "lim" || s == "max" || s == "min",
+        _ => false
+    }
+}
+
+fn fence(s: &str, left: bool) -> String {
+    let d = match s {
+        "{" => "\\{", "}" => "\\}", "" => ".", "‖" => "\\|", "⟨" => "\\langle", "⟩" => "\\rangle",
+        d => d
+    };
+    format!("{}{}", if left { "\\left" } else { "\\right" }, d)
+}
+
+fn write_table(rows: &[Vec<Element>], env: &str, out: &mut String) -> Option<()> {
+    out.push_str(&format!("\\begin{{{}}}", env));
+    for (i, row) in rows.iter().enumerate() {
+        if i > 0 { out.push_str(" \\\\ "); }
+        for (j, e) in row.iter().enumerate() {
+            if j > 0 { out.push_str(" & "); }
+            e.write(&Latex, out)?;
+        }
+    }
+    out.push_str(&format!("\\end{{{}}}", env));
+    Some(())
+}
+
+/// LaTeX source, as the `Element` tree is walked
+struct Latex;
+
+impl Notation for Latex {
+    fn leaf(&self, kind: Leaf, s: &str, out: &mut String) -> Option<()> {
+        match (kind, s) {
+            (Leaf::Operator, "*") => out.push_str("\\cdot "),
+            (Leaf::Operator, "=") | (Leaf::Operator, "+") | (Leaf::Operator, "-") | (Leaf::Operator, "<") | (Leaf::Operator, ">") => {
+                out.push(' ');
+                out.push_str(s);
+                out.push(' ');
+            },
+            _ => write_text(s, out)
+        }
+        Some(())
+    }
+
+    fn space(&self, out: &mut String) -> Option<()> {
+        out.push_str("\\,");
+        Some(())
+    }
+
+    fn group(&self, e: &Element, out: &mut String) -> Option<()> {
+        out.push('{');
+        e.write(self, out)?;
+        out.push('}');
+        Some(())
+    }
+
+    fn fraction(&self, numer: &Element, denom: &Element, out: &mut String) -> Option<()> {
+        out.push_str("\\frac");
+        self.group(numer, out)?;
+        self.group(denom, out)
+    }
+
+    fn root(&self, base: &Element, index: Option<&Element>, out: &mut String) -> Option<()> {
+        out.push_str("\\sqrt");
+        if let Some(i) = index {
+            out.push('[');
+            i.write(self, out)?;
+            out.push(']');
+        }
+        self.group(base, out)
+    }
+
+    fn fenced(&self, open: &str, close: &str, sep: &str, children: &[Element], out: &mut String) -> Option<()> {
+        if children.len() == 1 {
+            if let Element::Table(ref rows) = children[0] {
+                let env = match open { "(" => "pmatrix", "[" => "bmatrix", "|" => "vmatrix", _ => "matrix" };
+                return write_table(rows, env, out);
+            }
+        }
+        out.push_str(&fence(open, true));
+        let sep = sep.chars().next().unwrap_or(',');
+        for (i, c) in children.iter().enumerate() {
+            if i > 0 { out.push(sep); out.push(' '); }
+            c.write(self, out)?;
+        }
+        out.push_str(&fence(close, false));
+        Some(())
+    }
+
+    fn scripts(&self, base: &Element, sub: Option<&Element>, sup: Option<&Element>, out: &mut String) -> Option<()> {
+        write_base(base, out)?;
+        if let Some(s) = sub { out.push('_'); self.group(s, out)?; }
+        if let Some(s) = sup { out.push('^'); self.group(s, out)?; }
+        Some(())
+    }
+
+    /// limits go under and over large operators, and anything else is set under or over its base
+    fn stacked(&self, base: &Element, under: Option<&Element>, over: Option<&Element>, out: &mut String) -> Option<()> {
+        if is_large_operator(base) {
+            return self.scripts(base, under, over, out);
+        }
+        if let Some(o) = over {
+            out.push_str("\\overset");
+            self.group(o, out)?;
+            if under.is_some() { out.push('{'); }
+        }
+        if let Some(u) = under {
+            out.push_str("\\underset");
+            self.group(u, out)?;
+        }
+        self.group(base, out)?;
+        if under.is_some() && over.is_some() { out.push('}'); }
+        Some(())
+    }
+
+    fn table(&self, rows: &[Vec<Element>], out: &mut String) -> Option<()> {
+        write_table(rows, "matrix", out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_element;
+    use mathml::Element;
+
+    fn id(s: &str) -> Element { Element::Id(String::from(s), None, 0) }
+    fn num(s: &str) -> Element { Element::Number(String::from(s), None, 0) }
+    fn op(s: &str) -> Element { Element::Operator(String::from(s), None, 0) }
+    fn row(els: Vec<Element>) -> Element { Element::Row(els) }
+    fn fenced(open: &str, close: &str, children: Vec<Element>) -> Element {
+        Element::Fenced { open: String::from(open), close: String::from(close), seperator: String::from(","), children }
+    }
+
+    #[test]
+    fn fractions() {
+        let e = Element::Fraction { numer: Box::new(row(vec![id("x"), op("+"), num("1")])), denom: Box::new(num("2")), script: false };
+        assert_eq!(from_element(&e), "\\frac{x + 1}{2}");
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(from_element(&Element::Sqrt(Box::new(id("x")))), "\\sqrt{x}");
+        let e = Element::Root { base: Box::new(id("x")), index: Box::new(num("3")) };
+        assert_eq!(from_element(&e), "\\sqrt[3]{x}");
+    }
+
+    #[test]
+    fn scripts() {
+        let e = Element::Superscript { base: Box::new(id("x")), script: Box::new(num("2")) };
+        assert_eq!(from_element(&e), "x^{2}");
+        let e = Element::Subsuperscript { base: Box::new(id("a")), subscript: Box::new(id("i")), superscript: Box::new(num("2")) };
+        assert_eq!(from_element(&e), "a_{i}^{2}");
+        // a base that isn't a single token is braced so that the script applies to all of it
+        let e = Element::Superscript { base: Box::new(fenced("(", ")", vec![row(vec![id("x"), op("+"), num("1")])])), script: Box::new(num("2")) };
+        assert_eq!(from_element(&e), "{\\left(x + 1\\right)}^{2}");
+    }
+
+    #[test]
+    fn fences() {
+        assert_eq!(from_element(&fenced("[", "]", vec![id("a"), id("b")])), "\\left[a, b\\right]");
+        assert_eq!(from_element(&fenced("{", "}", vec![num("1")])), "\\left\\{1\\right\\}");
+    }
+
+    #[test]
+    fn matrices() {
+        let table = Element::Table(vec![vec![num("1"), num("2")], vec![num("3"), num("4")]]);
+        let e = fenced("(", ")", vec![table]);
+        assert_eq!(from_element(&e), "\\begin{pmatrix}1 & 2 \\\\ 3 & 4\\end{pmatrix}");
+    }
+
+    #[test]
+    fn large_operators() {
+        let e = Element::UnderOver {
+            base: Box::new(op("∑")),
+            under: Box::new(row(vec![id("k"), op("="), num("1")])),
+            over: Box::new(id("n"))
+        };
+        assert_eq!(from_element(&e), "\\sum_{k = 1}^{n}");
+        let e = Element::Under { base: Box::new(id("lim")), script: Box::new(row(vec![id("x"), op("→"), num("0")])) };
+        assert_eq!(from_element(&e), "\\lim_{x\\to0}");
+        // anything else stacked over a base isn't a limit, so it is set over it
+        let e = Element::Over { base: Box::new(id("v")), script: Box::new(op("→")) };
+        assert_eq!(from_element(&e), "\\overset{\\to}{v}");
+    }
+
+    #[test]
+    fn symbols() {
+        assert_eq!(from_element(&row(vec![id("𝜋"), id("x")])), "\\pi x");
+        assert_eq!(from_element(&row(vec![id("sin"), id("x")])), "\\sin x");
+    }
+}
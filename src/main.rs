@@ -3,6 +3,8 @@ extern crate winit;
 extern crate mio;
 extern crate regex;
 extern crate xml;
+extern crate clipboard;
+extern crate unicode_segmentation;
 
 use runic::*;
 use winit::*;
@@ -13,94 +15,210 @@ use std::io::{Read,Write};
 //use mio::net::TcpStream;
 use std::net::TcpStream;
 use std::fmt::Display;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::collections::VecDeque;
 
 use regex::Regex;
+use clipboard::{ClipboardProvider, ClipboardContext};
 
 mod mathml;
+mod latex;
+mod editor;
+mod cell;
+mod notebook;
+mod menu;
+mod syntax;
+mod markup;
+mod query;
+mod completion;
+mod help;
+mod recall;
+mod inspector;
+mod plot;
+mod surface;
+mod contour;
 
-struct Cell {
-    index: usize,
-    input: String,
-    output: Option<mathml::MathExpression>,
-    output_src: Option<String>,
-    input_layout: Option<TextLayout>
-}
-
-impl Cell {
-    fn empty(index: usize) -> Cell {
-        Cell {
-            index, input: String::new(),
-            output: None, output_src: None, input_layout: None
-        }
-    }
-
-    fn bounds(&self) -> Rect {
-       let ib = self.input_layout.as_ref().map(|ly| ly.bounds()).unwrap_or(Rect::wh(0.0, 0.0));
-       let ob = self.output.as_ref().map(|e| e.bounds()).unwrap_or(Rect::wh(0.0, 0.0));
-       Rect::wh(ib.w.max(ob.w), ib.h+ob.h+4.0)
-    }
+use editor::Cursor;
+use cell::{Cell, CellKind, EvalState, Fonts};
+use notebook::{CellOp, History};
+use menu::Menu;
+use syntax::Palette;
+use query::Query;
+use completion::Completions;
+use help::HelpPanel;
+use recall::InputHistory;
+use inspector::{Inspector, Binding, BindingKind};
+use plot::{Figure, Plot2d};
+use surface::Surface;
 
-    fn draw(&mut self, p: Point, rx: &mut RenderContext, fnt: &Font, math_fnt: &Font) {
-        let input_str = format!("(%{}) {}", self.index, self.input);
-        let ily = self.input_layout.get_or_insert_with(|| {
-            rx.new_text_layout(&input_str, &fnt, 4096.0, 256.0).expect("create text layout")
-        });
-        rx.draw_text_layout(p, ily);
-        let ib = ily.bounds();
-        if self.output_src.is_some() {
-            self.output = match mathml::MathExpression::from_mathml(self.output_src.take().unwrap().as_bytes(), rx, &math_fnt) {
-                Ok(o) => Some(o),
-                Err(e) => {
-                    println!("mathml error: {}", e);
-                    None
-                }
-            };
-        }
-        if let Some(ref o) = self.output {
-            let ob = o.bounds();
-            o.draw(p + Point::y(ib.h+4.0 + ob.h/2.0), rx);
-        }
-    }
-
-    fn draw_cursor(&self, p: Point, rx: &mut RenderContext, cursor_idx: usize) {
-        let cb = self.input_layout.as_ref().map(|ly| ly.char_bounds(cursor_idx+4+self.index/10)).unwrap().offset(p);
-        rx.set_color(Color::rgba(0.6, 0.6, 0.8, 0.9));
-        rx.draw_line(Point::xy(cb.x+cb.w, cb.y), Point::xy(cb.x+cb.w, cb.y+cb.h), 2.0);
-        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
-    }
+/// What the output context menu can do with the selected subexpression
+#[derive(Clone)]
+enum OutputAction {
+    Copy,
+    CopyLatex,
+    /// put the subexpression as the argument to a function in a new cell
+    Feed(&'static str),
+    /// write the cell's plot out as an SVG file
+    SavePlot,
+    CopyPlot
 }
 
 struct MaximaApp {
-    font: Font, math_font: Font,
+    fonts: Fonts,
+    palette: Palette,
     maxima_proc: Child,
     maxima_strm: TcpStream,
     cells: Vec<Cell>,
     current_cell: usize,
-    cursor_idx: usize,
+    /// the label Maxima's current `(%iN)` prompt is waiting on
+    prompt_index: usize,
+    /// the cell whose input was last sent, which receives the next output
+    evaluating: Option<usize>,
+    /// Maxima is working on some input and hasn't given a new prompt yet
+    busy: bool,
+    /// an error message arrived while evaluating the current input
+    error_seen: bool,
+    /// drop the rest of the queue when an evaluation fails
+    stop_on_error: bool,
+    /// the other end of a range of selected cells, with the current cell at the near end
+    cell_anchor: Option<usize>,
+    /// move focus to the new prompt cell when it arrives
+    follow_prompt: bool,
+    /// queries waiting for Maxima, which go ahead of queued cells
+    queries: VecDeque<Query>,
+    /// the query Maxima is answering, and what it has printed so far
+    querying: Option<Query>,
+    query_buf: String,
+    completions: Completions,
+    /// the completion popup, with the cell and input position of the word being completed
+    completion_menu: Option<(usize, usize, Menu<String>)>,
+    help: Option<HelpPanel>,
+    /// every input sent to Maxima, across sessions
+    inputs: InputHistory,
+    /// while stepping through past inputs: the cell, the entry it shows (none for the draft) and
+    /// what the cell held before, whose start the entries must match
+    recall: Option<(usize, Option<usize>, String)>,
+    /// an incremental search of past inputs: what has been typed and the entry it found
+    search: Option<(String, Option<usize>)>,
+    search_layout: Option<TextLayout>,
+    show_inspector: bool,
+    inspector: Inspector,
+    cursor: Cursor,
+    history: History,
+    modifiers: ModifiersState,
+    mouse_pos: Point,
+    dragging: bool,
+    last_click: Option<Instant>,
     input_regex: Regex,
     output_regex: Regex,
-    viewport_start: usize,
+    error_regex: Regex,
+    linear_regex: Regex,
+    tex_regex: Regex,
+    reply_regex: Regex,
+    item_regex: Regex,
+    /// how far the notebook is scrolled down, in pixels
+    scroll: f32,
+    /// height of the whole notebook and of the window, as of the last paint
+    content_h: f32,
+    view_w: f32,
+    view_h: f32,
+    /// scroll the current cell into view at the next paint, if it isn't already
+    scroll_to_current: bool,
+    dragging_scrollbar: bool,
+    /// the cells drawn in the last paint, which are the only ones whose positions are current
+    on_screen: Vec<usize>,
+    clipboard: ClipboardContext,
+    /// where Maxima has gnuplot write plots, to be picked up when the input that made them is done
+    plot_file: PathBuf,
+    /// the start of a plot whose end hasn't arrived yet
+    partial_plot: String,
+    plot_regex: Regex,
+    surface_regex: Regex,
+    contour_regex: Regex,
+    /// a 3D plot being turned by dragging, and where the mouse was last
+    rotating: Option<(usize, Point)>,
+    notebook_path: Option<PathBuf>,
+    show_outline: bool,
+    /// the context menu for an output subexpression and the cell it belongs to
+    context_menu: Option<(usize, Menu<OutputAction>)>,
+    /// text of each heading in the outline panel, rebuilt after edits
+    outline: Option<Vec<(usize, TextLayout)>>,
+    /// where each outline entry was last drawn, for clicking on
+    outline_hits: Vec<(Rect, usize)>
+}
+
+/// Lisp run by Maxima at startup: load the MathML printer, replace `displa` so that every output
+/// is sent as its label followed by MathML and then its 1D input form (from `string`) wrapped in
+/// <linear></linear>, and connect back to us. With `request_tex` set, each output is also followed
+/// by Maxima's own `tex1` rendering wrapped in <tex></tex>. With `native_plots` set, `plot2d`,
+/// `plot3d`, `implicit_plot` and `contour_plot` are replaced so that they send us points to draw;
+/// otherwise plots are sent to `plot_file` as SVG instead of a gnuplot window
+fn bootstrap_lisp(request_tex: bool, native_plots: bool, plot_file: &Path) -> String {
+    let linear = "(princ \"<linear>\") (princ ($string (caddr exp))) (princ \"</linear>\") (terpri)";
+    let tex = if request_tex { "(princ \"<tex>\") (princ ($tex1 (caddr exp))) (princ \"</tex>\") (terpri)" } else { "" };
+    let plot = plot_file.to_string_lossy().replace('\\', "/").replace('"', "\\\"");
+    format!(":lisp ($load \"mathml\") (defun displa(exp) (print (cadr exp)) (mathml1 (caddr exp)) (terpri) {} {}) \
+             ($set_plot_option '((mlist) $svg_file \"{}\")) {} (setup-client 4444)\n",
+            linear, tex, plot, if native_plots {
+                format!("{} {} {} {}", plot::PLOT_LISP, surface::SURFACE_LISP, contour::CONTOUR_LISP, plot::INSTALL_LISP)
+            } else {
+                String::new()
+            })
 }
 
 impl MaximaApp {
-    fn new(rx: &mut RenderContext) -> Result<MaximaApp, Box<Error>> {
-        let font = rx.new_font("Fira Code", 18.0, FontWeight::Regular, FontStyle::Normal)?;
-        let math_font = rx.new_font("Cambria Math", 18.0, FontWeight::Regular, FontStyle::Normal)?;
+    fn new(rx: &mut RenderContext, request_tex: bool, native_plots: bool, stop_on_error: bool, notebook_path: Option<PathBuf>,
+           palette: Palette) -> Result<MaximaApp, Box<Error>> {
+        let fonts = Fonts::new(rx)?;
+        // a history that can't be read is left alone, and this session's inputs aren't kept
+        let inputs = InputHistory::open(recall::data_dir().map(|d| d.join("history"))).unwrap_or_else(|e| {
+            println!("error reading input history: {}", e);
+            InputHistory::open(None).unwrap()
+        });
+        let cells = match notebook_path {
+            Some(ref p) if p.exists() => notebook::load(p)?,
+            _ => Vec::new()
+        };
+        let plot_dir = std::env::temp_dir().join(format!("rmaxima-{}", std::process::id()));
+        fs::create_dir_all(&plot_dir)?;
+        let plot_file = plot_dir.join("plot.svg");
+        let bootstrap = bootstrap_lisp(request_tex, native_plots, &plot_file);
         let mut proc = Command::new("C:/maxima-5.41.0a/clisp-2.49/base/lisp.exe")
             .args(vec!["-q", "-M", "C:/maxima-5.41.0a/lib/maxima/5.41.0a_dirty/binary-clisp/maxima.mem",
-                  "", "--", "-r", ":lisp ($load \"mathml\") (defun displa(exp) (print (cadr exp)) (mathml1 (caddr exp)) (terpri)) (setup-client 4444)\n"])
+                  "", "--", "-r", &bootstrap[..]])
             .stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
         let listener = std::net::TcpListener::bind("127.0.0.1:4444").unwrap();
         let mut strm = listener.accept()?.0;
         strm.set_nonblocking(true)?;
         Ok(MaximaApp {
-            font, math_font,
+            fonts, palette,
             maxima_proc: proc,
             maxima_strm: strm,
-            cells: Vec::new(), current_cell: 0, cursor_idx: 0,
+            cells, current_cell: 0, cursor: Cursor::new(),
+            prompt_index: 0, evaluating: None, follow_prompt: true,
+            busy: true, error_seen: false, stop_on_error, cell_anchor: None,
+            history: History::new(), modifiers: ModifiersState::default(),
+            mouse_pos: Point::xy(0.0, 0.0), dragging: false, last_click: None,
             input_regex: Regex::new(r"\(%i(\d+)\)")?,
-            output_regex: Regex::new(r"(?ms)\$%O(\d+)\s[[:cntrl:]]*(.*</math>)")?,
-            viewport_start: 0
+            output_regex: Regex::new(r"(?ms)\$%O(\d+)\s[[:cntrl:]]*(.*?</math>)")?,
+            error_regex: Regex::new(r"(?m)^-- an error|^incorrect syntax|^Maxima encountered a Lisp error")?,
+            linear_regex: Regex::new(r"(?ms)\A\s*<linear>(.*?)</linear>")?,
+            tex_regex: Regex::new(r"(?ms)\A\s*<tex>(.*?)</tex>")?,
+            reply_regex: Regex::new(r"(?ms)<reply>\s*(.*?)</reply>")?,
+            item_regex: Regex::new(r"(?ms)<item (\w) ([^>]*)>(.*?)</item>")?,
+            queries: vec![Query::Symbols].into_iter().collect(), querying: None, query_buf: String::new(),
+            completions: Completions::new(), completion_menu: None, help: None,
+            inputs, recall: None, search: None, search_layout: None,
+            show_inspector: false, inspector: Inspector::new(),
+            scroll: 0.0, content_h: 0.0, view_w: 0.0, view_h: 0.0, scroll_to_current: true, dragging_scrollbar: false,
+            on_screen: Vec::new(),
+            clipboard: ClipboardProvider::new()?, plot_file, partial_plot: String::new(),
+            plot_regex: Regex::new(r"(?ms)<plot>(.*?)</plot>")?,
+            surface_regex: Regex::new(r"(?ms)<surface>(.*?)</surface>")?, rotating: None,
+            contour_regex: Regex::new(r"(?ms)<contour>(.*?)</contour>")?,
+            notebook_path, show_outline: false, context_menu: None, outline: None, outline_hits: Vec::new()
         })
     }
 }
@@ -123,30 +241,889 @@ impl MaximaApp {
                 }
             }
         }
-        if new_in.len() > 0 {
+        if !self.partial_plot.is_empty() {
+            new_in = ::std::mem::replace(&mut self.partial_plot, String::new()) + &new_in;
+        }
+        // plots are long enough to arrive in pieces, so hold on to one until all of it is here
+        for &(open, close) in &[("<plot>", "</plot>"), ("<surface>", "</surface>"), ("<contour>", "</contour>")] {
+            if let Some(start) = new_in.rfind(open) {
+                if !new_in[start..].contains(close) {
+                    self.partial_plot = new_in.split_off(start);
+                    break;
+                }
+            }
+        }
+        if new_in.len() > 0 && self.querying.is_some() {
+            // replies can be long, so collect them until the prompt comes back
+            self.query_buf += &new_in;
+            // documentation quotes example prompts, so only look for the real one after the reply,
+            // or after an error if the query failed
+            let prompt = self.query_buf.find("</reply>")
+                .or_else(|| self.error_regex.find(&self.query_buf).map(|m| m.start()))
+                .and_then(|end| self.input_regex.captures_iter(&self.query_buf[end..]).last())
+                .map(|c| c[1].parse().expect("parse input index"));
+            if let Some(index) = prompt {
+                let q = self.querying.take().unwrap();
+                let text = ::std::mem::replace(&mut self.query_buf, String::new());
+                match self.reply_regex.captures(&text) {
+                    Some(reply) => self.answer(q, &reply[1]),
+                    None => println!("no reply to {:?} in \"{}\"", q, text)
+                }
+                self.prompt_index = index;
+                self.busy = false;
+            }
+        } else if new_in.len() > 0 {
             println!("in: \"{}\"", new_in);
+            if self.busy && self.error_regex.is_match(&new_in) {
+                self.error_seen = true;
+            }
+            for p in self.plot_regex.captures_iter(&new_in) {
+                if let Some(i) = self.evaluating {
+                    self.cells[i].set_plot(Plot2d::parse(&p[1]).figure());
+                }
+            }
+            for p in self.surface_regex.captures_iter(&new_in) {
+                match (self.evaluating, Surface::parse(&p[1])) {
+                    (Some(i), Some(s)) => self.cells[i].set_surface(s),
+                    (_, None) => println!("bad surface data: \"{}\"", &p[1]),
+                    _ => {}
+                }
+            }
+            for p in self.contour_regex.captures_iter(&new_in) {
+                match (self.evaluating, contour::parse(&p[1])) {
+                    (Some(i), Some(c)) => self.cells[i].set_plot(c.figure()),
+                    (_, None) => println!("bad contour data: \"{}\"", &p[1]),
+                    _ => {}
+                }
+            }
             for outputs in self.output_regex.captures_iter(&new_in) {
                 let index = outputs[1].parse().expect("parse output index");
-                let mut found = false;
-                for cell in self.cells.iter_mut() {
-                    if cell.index == index {
-                        cell.output_src = Some(String::from(outputs[2].trim()));
-                        cell.output = None;
-                        found = true;
-                        break;
+                let src = String::from(outputs[2].trim());
+                let rest = &new_in[outputs.get(0).unwrap().end()..];
+                let (linear, rest) = match self.linear_regex.captures(rest) {
+                    Some(l) => (Some(String::from(l[1].trim())), &rest[l.get(0).unwrap().end()..]),
+                    None => (None, rest)
+                };
+                let tex = self.tex_regex.captures(rest).map(|t| String::from(t[1].trim()));
+                // prefer the cell we just evaluated, since it may have been re-evaluated out of order
+                let target = self.evaluating.filter(|&i| self.cells.get(i).map_or(false, |c| c.index == Some(index)))
+                    .or_else(|| self.cells.iter().position(|c| c.index == Some(index)));
+                match target {
+                    Some(i) => self.cells[i].set_output(src, linear, tex),
+                    None => {
+                        let mut c = Cell::empty(Some(index));
+                        c.set_output(src, linear, tex);
+                        self.cells.push(c);
                     }
                 }
-                if !found {
-                    let mut c = Cell::empty(index);
-                    c.output_src = Some(String::from(outputs[2].trim()));
-                    self.cells.push(c);
-                }
             }
-            if let Some(ref inp) = self.input_regex.captures(&new_in) {
+            if let Some(inp) = self.input_regex.captures_iter(&new_in).last() {
                 let index = inp[1].parse().expect("parse output index");
-                self.cells.push(Cell::empty(index));
-                self.current_cell = self.cells.len()-1;
-                self.cursor_idx = 0;
+                self.prompt_index = index;
+                if let Some(i) = self.evaluating.take() {
+                    self.cells[i].finish(self.error_seen);
+                    if let Some(f) = self.take_plot() {
+                        self.cells[i].set_plot(f);
+                    }
+                    if self.error_seen && self.stop_on_error {
+                        self.cancel_queue();
+                    }
+                    // once a run of evaluations is over, catch up on what it defined
+                    let more = self.cells.iter().any(|c| c.state == EvalState::Queued);
+                    if !more && !self.queries.contains(&Query::Definitions) {
+                        self.queries.push_back(Query::Definitions);
+                    }
+                    if !more && self.show_inspector && !self.queries.contains(&Query::Inspect) {
+                        self.queries.push_back(Query::Inspect);
+                    }
+                }
+                self.busy = false;
+                self.error_seen = false;
+                // a fresh prompt cell left over from before a re-evaluation just takes the new label
+                let reuse = self.cells.last().map(|c| c.kind == CellKind::Code && c.input.is_empty() && c.output_src.is_none()).unwrap_or(false);
+                if reuse {
+                    self.cells.last_mut().unwrap().set_index(index);
+                } else {
+                    self.cells.push(Cell::empty(Some(index)));
+                }
+                if self.follow_prompt {
+                    self.current_cell = self.cells.len()-1;
+                    self.cursor = Cursor::new();
+                    self.scroll_to_current = true;
+                }
+            }
+        }
+        self.run_queue();
+    }
+}
+
+impl MaximaApp {
+    /// read the plot the last input made, if it made one, removing the file so that it isn't
+    /// mistaken for the next input's
+    fn take_plot(&mut self) -> Option<Figure> {
+        if !self.plot_file.exists() { return None; }
+        let fig = File::open(&self.plot_file).map_err(|e| Box::new(e) as Box<Error>).and_then(Figure::from_svg);
+        if let Err(e) = fs::remove_file(&self.plot_file) {
+            println!("error removing plot file: {}", e);
+        }
+        match fig {
+            Ok(f) => Some(f),
+            Err(e) => {
+                println!("error reading plot: {}", e);
+                None
+            }
+        }
+    }
+
+    /// queue a cell to be sent to Maxima
+    fn evaluate(&mut self, cell: usize) {
+        // there is nothing to send for a blank cell, and a lone `;` would only use up a label
+        if self.cells[cell].kind != CellKind::Code || self.cells[cell].input.trim().is_empty() { return; }
+        // catch unbalanced input here, since Maxima would just sit waiting for the rest of it
+        if let Err(e) = syntax::check_balance(&self.cells[cell].input) {
+            let at = e.position();
+            let line = self.cells[cell].input[..at].matches('\n').count() + 1;
+            self.cells[cell].set_warning(Some(format!("not sent: {} on line {}", e, line)));
+            if cell == self.current_cell {
+                self.cursor.move_to(at, false);
+            }
+            return;
+        }
+        self.cells[cell].queue();
+        self.run_queue();
+    }
+
+    /// queue every code cell in `cells`, in notebook order
+    fn evaluate_range(&mut self, cells: std::ops::Range<usize>) {
+        for i in cells {
+            self.evaluate(i);
+        }
+    }
+
+    /// if Maxima is waiting for input, send it the first query or failing that the first queued cell
+    fn run_queue(&mut self) {
+        if self.busy { return; }
+        if let Some(q) = self.queries.pop_front() {
+            write!(self.maxima_strm, "{}", q.command()).expect("write stream");
+            self.querying = Some(q);
+            self.busy = true;
+            return;
+        }
+        if let Some(cell) = self.cells.iter().position(|c| c.state == EvalState::Queued) {
+            // a plot left over from a query or an input that failed isn't this input's
+            if self.plot_file.exists() {
+                if let Err(e) = fs::remove_file(&self.plot_file) {
+                    println!("error removing plot file: {}", e);
+                }
+            }
+            {
+                // Maxima waits for the rest of a statement that has no terminator, so give it one
+                let input = &self.cells[cell].input;
+                let t = input.trim_right().chars().last();
+                let end = if t == Some(';') || t == Some('$') { "" } else { ";" };
+                write!(self.maxima_strm, "{}{}\n", input, end).expect("write stream");
+            }
+            if let Err(e) = self.inputs.add(&self.cells[cell].input) {
+                println!("error saving input history: {}", e);
+            }
+            let index = self.prompt_index;
+            self.cells[cell].set_index(index);
+            self.cells[cell].start();
+            self.evaluating = Some(cell);
+            self.busy = true;
+            self.follow_prompt = cell == self.cells.len()-1 && cell == self.current_cell;
+        }
+    }
+
+    /// take in Maxima's reply to a query
+    fn answer(&mut self, q: Query, reply: &str) {
+        match q {
+            Query::Symbols => self.completions.set_symbols(reply),
+            Query::Definitions => self.completions.set_definitions(reply),
+            Query::Describe(topic) => self.help = Some(HelpPanel::new(topic, help::parse(reply))),
+            Query::Inspect => {
+                let bindings = self.item_regex.captures_iter(reply).filter_map(|c| {
+                    BindingKind::from_tag(&c[1]).map(|k| Binding::new(k, String::from(&c[2]), String::from(c[3].trim())))
+                }).collect();
+                self.inspector.set(bindings);
+            }
+        }
+    }
+
+    /// complete the word before the caret from the names Maxima and the notebook know about,
+    /// offering a popup when there is more than one way to go
+    fn complete(&mut self) {
+        let cell = self.current_cell;
+        if self.cells[cell].kind != CellKind::Code { return; }
+        let pos = self.cursor.pos;
+        let start = editor::word_start(&self.cells[cell].input, pos);
+        if start == pos || self.cursor.selection().is_some() {
+            self.completion_menu = None;
+            return;
+        }
+        let prefix = self.cells[cell].input[start..pos].to_owned();
+        let found = self.candidates(&prefix);
+        if found.is_empty() {
+            self.completion_menu = None;
+            return;
+        }
+        // fill in as much as all of the candidates agree on
+        let common = found.iter().skip(1).fold(found[0].0.clone(), |common, &(ref name, _)| {
+            let n = common.chars().zip(name.chars()).take_while(|&(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+            String::from(&common[..n])
+        });
+        if common.len() > prefix.len() {
+            self.accept_completion(start, &common);
+        }
+        if found.len() == 1 {
+            self.completion_menu = None;
+        } else {
+            self.show_completions(cell, start, found);
+        }
+    }
+
+    fn toggle_inspector(&mut self) {
+        self.show_inspector = !self.show_inspector;
+        if self.show_inspector && !self.queries.contains(&Query::Inspect) {
+            self.queries.push_back(Query::Inspect);
+            self.run_queue();
+        }
+    }
+
+    /// ask Maxima for the documentation of the identifier at the caret, or close the help panel if there isn't one
+    fn lookup_help(&mut self) {
+        let topic = {
+            let input = &self.cells[self.current_cell].input;
+            let pos = self.cursor.pos;
+            String::from(&input[editor::word_start(input, pos)..editor::word_end(input, pos)])
+        };
+        let topic = String::from(topic.trim_left_matches('%'));
+        if topic.is_empty() || self.help.as_ref().map_or(false, |h| h.topic == topic) {
+            self.help = None;
+        } else {
+            self.queries.push_back(Query::Describe(topic));
+            self.run_queue();
+        }
+    }
+
+    /// names starting with `prefix`, including ones defined anywhere in the notebook
+    fn candidates(&self, prefix: &str) -> Vec<(String, completion::CandidateKind)> {
+        let notebook: Vec<_> = self.cells.iter().filter(|c| c.kind == CellKind::Code)
+            .flat_map(|c| completion::defined_names(&c.input)).collect();
+        self.completions.candidates(prefix, &notebook)
+    }
+
+    fn show_completions(&mut self, cell: usize, start: usize, found: Vec<(String, completion::CandidateKind)>) {
+        let items = found.into_iter().take(12)
+            .map(|(name, kind)| (format!("{}   {}", name, kind.name()), name)).collect();
+        let mut menu = Menu::new(Point::xy(0.0, 0.0), items);
+        menu.selected = Some(0);
+        self.completion_menu = Some((cell, start, menu));
+    }
+
+    /// narrow the completion popup down as the word is typed, closing it once the caret leaves the word
+    fn refresh_completions(&mut self) {
+        let (cell, start) = match self.completion_menu {
+            Some((cell, start, _)) => (cell, start),
+            None => return
+        };
+        let pos = self.cursor.pos;
+        let input = &self.cells[cell].input;
+        if cell != self.current_cell || pos <= start || editor::word_start(input, pos) != start {
+            self.completion_menu = None;
+            return;
+        }
+        let found = self.candidates(&input[start..pos]);
+        if found.is_empty() {
+            self.completion_menu = None;
+        } else {
+            self.show_completions(cell, start, found);
+        }
+    }
+
+    /// replace the word from `start` up to the caret with `name`
+    fn accept_completion(&mut self, start: usize, name: &str) {
+        let pos = self.cursor.pos;
+        self.text_edit(|c, t| {
+            c.move_to(start, false);
+            c.move_to(pos, true);
+            c.insert(t, name);
+        });
+    }
+
+    /// replace the current cell's input with an older (or newer) input from the history. Only
+    /// inputs starting with what the cell held to begin with are visited, and going past the
+    /// newest brings that back
+    fn recall(&mut self, older: bool) {
+        let cell = self.current_cell;
+        let (at, draft) = match self.recall.take() {
+            Some((c, at, draft)) if c == cell => (at, draft),
+            _ => (None, self.cells[cell].input.clone())
+        };
+        let next = if older {
+            self.inputs.older(at, draft.trim())
+        } else {
+            at.and_then(|i| self.inputs.newer(i, draft.trim()))
+        };
+        let text = match next {
+            Some(i) => String::from(self.inputs.get(i)),
+            None if older || at.is_none() => {
+                // nothing further that way
+                self.recall = Some((cell, at, draft));
+                return;
+            },
+            None => draft.clone()
+        };
+        self.text_edit(|c, t| {
+            *t = text;
+            c.move_to(t.len(), false);
+        });
+        if next.is_some() {
+            self.recall = Some((cell, next, draft));
+        }
+    }
+
+    /// look for the search text again, starting from the newest input or, with `again`, from
+    /// before the last match
+    fn find_input(&mut self, again: bool) {
+        if let Some((ref query, ref mut found)) = self.search {
+            let before = if again { *found } else { None };
+            let hit = self.inputs.search(query, before);
+            if hit.is_some() || !again { *found = hit; }
+        }
+        self.search_layout = None;
+    }
+
+    /// handle a key while searching, returning false if the key ends the search and should then
+    /// go on to do what it normally does
+    fn search_key(&mut self, k: VirtualKeyCode, mods: ModifiersState) -> bool {
+        match k {
+            VirtualKeyCode::R if mods.ctrl => self.find_input(true),
+            VirtualKeyCode::Back => {
+                if let Some((ref mut query, _)) = self.search { query.pop(); }
+                self.find_input(false);
+            },
+            VirtualKeyCode::Return => {
+                let found = self.search.take().and_then(|(_, f)| f);
+                if let Some(i) = found {
+                    let text = String::from(self.inputs.get(i));
+                    self.text_edit(|c, t| {
+                        *t = text;
+                        c.move_to(t.len(), false);
+                    });
+                }
+            },
+            VirtualKeyCode::Escape => self.search = None,
+            VirtualKeyCode::Up | VirtualKeyCode::Down | VirtualKeyCode::Left | VirtualKeyCode::Right |
+            VirtualKeyCode::Home | VirtualKeyCode::End | VirtualKeyCode::PageUp | VirtualKeyCode::PageDown |
+            VirtualKeyCode::Tab => {
+                self.search = None;
+                return false;
+            },
+            // the text of the search arrives as characters
+            _ => {}
+        }
+        true
+    }
+
+    fn draw_search(&mut self, rx: &mut RenderContext) {
+        let bnds = rx.bounds();
+        if self.search_layout.is_none() {
+            let text = match self.search {
+                Some((ref query, Some(i))) => format!("search: {}   →   {}", query, self.inputs.get(i).lines().next().unwrap_or("")),
+                Some((ref query, None)) => format!("search: {}   (no match)", query),
+                None => return
+            };
+            self.search_layout = rx.new_text_layout(&text, &self.fonts.code, bnds.w - 16.0, 64.0).ok();
+        }
+        if let Some(ref ly) = self.search_layout {
+            let h = ly.bounds().h + 8.0;
+            rx.set_color(Color::rgba(0.1, 0.1, 0.12, 0.95));
+            rx.fill_rect(Rect::xywh(0.0, bnds.h - h, bnds.w, h));
+            rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+            rx.draw_text_layout(Point::xy(8.0, bnds.h - h + 4.0), ly);
+        }
+    }
+
+    fn cancel_queue(&mut self) {
+        for c in self.cells.iter_mut() {
+            c.cancel();
+        }
+    }
+
+    /// the selected range of cells, which is just the current cell unless a range has been marked
+    fn selected_cells(&self) -> std::ops::Range<usize> {
+        let a = self.cell_anchor.unwrap_or(self.current_cell);
+        a.min(self.current_cell)..a.max(self.current_cell)+1
+    }
+
+    /// make a structural change to the notebook, remembering how to undo it
+    fn edit(&mut self, op: CellOp) {
+        let (inv, focus) = op.apply(&mut self.cells);
+        self.history.record(notebook::Command::Structure(inv));
+        self.outline = None;
+        self.cell_anchor = None;
+        // cells have shifted, so find the evaluating cell again
+        self.evaluating = self.cells.iter().position(|c| c.state == EvalState::Running);
+        self.focus_cell(focus, false);
+    }
+
+    /// edit the current cell's input, remembering how to undo it
+    fn text_edit<F: FnOnce(&mut Cursor, &mut String)>(&mut self, f: F) {
+        let cell = self.current_cell;
+        let old = self.cells[cell].input.clone();
+        f(&mut self.cursor, &mut self.cells[cell].input);
+        self.cells[cell].input_layout = None;
+        self.cells[cell].set_warning(None);
+        self.scroll_to_current = true;
+        self.recall = None;
+        if let Some(inv) = notebook::Command::text_diff(cell, &old, &self.cells[cell].input) {
+            self.history.record(inv);
+            if self.cells[cell].kind.heading_level().is_some() { self.outline = None; }
+        }
+    }
+
+    fn undo(&mut self, redo: bool) {
+        let focus = if redo { self.history.redo(&mut self.cells) } else { self.history.undo(&mut self.cells) };
+        if let Some((cell, caret)) = focus {
+            self.evaluating = self.cells.iter().position(|c| c.state == EvalState::Running);
+            self.outline = None;
+            self.focus_cell(cell, false);
+            self.cursor.pos = caret;
+        }
+    }
+
+    fn cell_command(&mut self, k: VirtualKeyCode, mods: ModifiersState) {
+        let cell = self.current_cell;
+        let n = self.cells.len();
+        if mods.shift {
+            // extend the range of selected cells
+            let next = match k {
+                VirtualKeyCode::Up => self.neighbour(cell, false),
+                VirtualKeyCode::Down => self.neighbour(cell, true),
+                _ => None
+            };
+            if let Some(next) = next {
+                self.cell_anchor = Some(self.cell_anchor.unwrap_or(cell));
+                self.current_cell = next;
+                self.cursor = Cursor::new();
+            }
+            return;
+        }
+        match k {
+            VirtualKeyCode::A => self.edit(CellOp::Insert { at: cell, cell: Cell::empty(None) }),
+            VirtualKeyCode::B => self.edit(CellOp::Insert { at: cell+1, cell: Cell::empty(None) }),
+            VirtualKeyCode::D if n > 1 => self.edit(CellOp::Remove { at: cell }),
+            VirtualKeyCode::Up if cell > 0 => self.edit(CellOp::Move { from: cell, to: cell-1 }),
+            VirtualKeyCode::Down if cell+1 < n => self.edit(CellOp::Move { from: cell, to: cell+1 }),
+            VirtualKeyCode::S => {
+                let pos = self.cursor.pos;
+                self.edit(CellOp::Split { at: cell, pos })
+            },
+            VirtualKeyCode::M if cell+1 < n => self.edit(CellOp::Join { at: cell, sep: String::from("\n") }),
+            VirtualKeyCode::Key1 => self.edit(CellOp::SetKind { at: cell, kind: CellKind::Code }),
+            VirtualKeyCode::Key2 => self.edit(CellOp::SetKind { at: cell, kind: CellKind::Text }),
+            VirtualKeyCode::Key3 => self.edit(CellOp::SetKind { at: cell, kind: CellKind::Title }),
+            VirtualKeyCode::Key4 => self.edit(CellOp::SetKind { at: cell, kind: CellKind::Section }),
+            VirtualKeyCode::Key5 => self.edit(CellOp::SetKind { at: cell, kind: CellKind::Subsection }),
+            VirtualKeyCode::C => self.cells[cell].toggle_collapsed(),
+            _ => {}
+        }
+    }
+
+    /// the nearest visible cell before (`forward` false) or after `cell`
+    fn neighbour(&self, cell: usize, forward: bool) -> Option<usize> {
+        let visible = notebook::visibility(&self.cells);
+        if forward {
+            (cell+1..self.cells.len()).find(|&i| visible[i])
+        } else {
+            (0..cell).rev().find(|&i| visible[i])
+        }
+    }
+
+    /// expand whichever collapsed headings are hiding `cell`
+    fn reveal(&mut self, cell: usize) {
+        while !notebook::visibility(&self.cells)[cell] {
+            match (0..cell).rev().find(|&i| self.cells[i].collapsed) {
+                Some(h) => self.cells[h].toggle_collapsed(),
+                None => break
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        if let Some(ref p) = self.notebook_path {
+            if let Err(e) = notebook::save(p, &self.cells) {
+                println!("error saving notebook: {}", e);
+            }
+        }
+    }
+
+    /// reload the notebook from disk, replacing every cell but the one at Maxima's prompt
+    fn open(&mut self) {
+        let loaded = match self.notebook_path {
+            Some(ref p) => notebook::load(p),
+            None => return
+        };
+        match loaded {
+            Ok(cells) => {
+                self.cells = cells;
+                self.cells.push(Cell::empty(Some(self.prompt_index)));
+                self.history = History::new();
+                self.evaluating = None;
+                self.outline = None;
+                self.scroll = 0.0;
+                let last = self.cells.len()-1;
+                self.focus_cell(last, false);
+            },
+            Err(e) => println!("error opening notebook: {}", e)
+        }
+    }
+
+    fn draw_outline(&mut self, rx: &mut RenderContext) {
+        let bnds = rx.bounds();
+        if self.outline.is_none() {
+            let mut entries = Vec::new();
+            for (i, c) in self.cells.iter().enumerate() {
+                if let Some(level) = c.kind.heading_level() {
+                    let text = format!("{}{}", "  ".repeat(level), c.input.lines().next().unwrap_or(""));
+                    entries.push((i, rx.new_text_layout(&text, &self.fonts.text, 240.0, 64.0).expect("create text layout")));
+                }
+            }
+            self.outline = Some(entries);
+        }
+        let panel = Rect::xywh(bnds.w - 256.0, 0.0, 256.0, bnds.h);
+        rx.set_color(Color::rgba(0.1, 0.1, 0.12, 0.95));
+        rx.fill_rect(panel);
+        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+        self.outline_hits.clear();
+        let mut p = Point::xy(panel.x + 8.0, 8.0);
+        for &(i, ref ly) in self.outline.as_ref().unwrap() {
+            let b = ly.bounds();
+            rx.draw_text_layout(p, ly);
+            self.outline_hits.push((Rect::xywh(panel.x, p.y, panel.w, b.h), i));
+            p.y += b.h + 4.0;
+        }
+    }
+
+    /// move focus to another cell, with the caret at its start or end
+    fn focus_cell(&mut self, cell: usize, at_end: bool) {
+        self.current_cell = cell;
+        self.cursor = Cursor::new();
+        if at_end {
+            self.cursor.pos = self.cells[cell].input.len();
+        }
+        self.scroll_to_current = true;
+    }
+
+    fn scroll_by(&mut self, dy: f32) {
+        self.scroll = (self.scroll + dy).max(0.0).min((self.content_h - self.view_h).max(0.0));
+    }
+
+    /// the scrollbar's track and thumb, if the notebook is taller than the window
+    fn scrollbar(&self, bnds: Rect) -> Option<(Rect, Rect)> {
+        if self.content_h <= bnds.h { return None; }
+        let track = Rect::xywh(bnds.w - 10.0, 0.0, 10.0, bnds.h);
+        let thumb_h = (bnds.h / self.content_h * bnds.h).max(16.0);
+        let thumb_y = self.scroll / (self.content_h - bnds.h) * (bnds.h - thumb_h);
+        Some((track, Rect::xywh(track.x + 2.0, thumb_y, 6.0, thumb_h)))
+    }
+
+    /// scroll so that the scrollbar thumb is centred on window height `y`
+    fn drag_scrollbar(&mut self, y: f32) {
+        let s = y / self.view_h * self.content_h - self.view_h/2.0;
+        let d = s - self.scroll;
+        self.scroll_by(d);
+    }
+
+    fn set_clipboard(&mut self, s: String) {
+        if let Err(e) = self.clipboard.set_contents(s) {
+            println!("clipboard error: {}", e);
+        }
+    }
+
+    /// copy the output as LaTeX, converted from its MathML or, with `maxima` set, as Maxima's own
+    /// `tex1` gives it, which is only there when started with `--tex`
+    fn copy_latex(&mut self, maxima: bool) {
+        let tx = {
+            let cell = &self.cells[self.current_cell];
+            if maxima { cell.output_tex.clone() } else { cell.latex() }
+        };
+        if let Some(tx) = tx { self.set_clipboard(tx); }
+    }
+
+    fn copy_linear(&mut self) {
+        let s = self.cells[self.current_cell].output_linear.clone();
+        if let Some(s) = s { self.set_clipboard(s); }
+    }
+
+    fn copy_mathml(&mut self) {
+        let s = self.cells[self.current_cell].output_src.clone();
+        if let Some(s) = s { self.set_clipboard(s); }
+    }
+
+    fn copy_text(&mut self) {
+        let s = self.cells[self.current_cell].output.as_ref().map(|o| o.plain_text());
+        if let Some(s) = s { self.set_clipboard(s); }
+    }
+
+    /// copy the selected input, or failing that a selected piece of output, or else all of the input
+    fn copy_input(&mut self) {
+        let s = {
+            let c = &self.cells[self.current_cell];
+            match (self.cursor.selected_text(&c.input), c.selected_output()) {
+                (Some(s), _) => String::from(s),
+                (None, Some(e)) => match e.input_form() {
+                    Some(s) => s,
+                    None => { println!("the selected output has no input form"); return; }
+                },
+                (None, None) => c.input.clone()
+            }
+        };
+        self.set_clipboard(s);
+    }
+
+    fn cut_input(&mut self) {
+        self.copy_input();
+        self.text_edit(|c, t| {
+            if c.selection().is_none() {
+                c.select_all(t);
+            }
+            c.delete_selection(t);
+        });
+    }
+
+    fn paste(&mut self) {
+        let s = match self.clipboard.get_contents() {
+            Ok(s) => s,
+            Err(e) => { println!("clipboard error: {}", e); return; }
+        };
+        let s: String = s.chars().filter(|&c| !c.is_control() || c == '\n' || c == '\t')
+            .map(|c| if c == '\t' { ' ' } else { c }).collect();
+        self.text_edit(|c, t| c.insert(t, &s));
+    }
+
+    fn mouse_down(&mut self) {
+        let bnds = Rect::wh(self.view_w, self.view_h);
+        let over_help = self.help.as_ref().map_or(false, |h| h.rect(bnds).contains(self.mouse_pos));
+        if over_help { return; }
+        if self.show_inspector && self.inspector.rect(bnds).contains(self.mouse_pos) {
+            let name = self.inspector.hit(self.mouse_pos).map(String::from);
+            if let Some(name) = name {
+                self.text_edit(|c, t| c.insert(t, &name));
+            }
+            return;
+        }
+        if let Some((_, start, menu)) = self.completion_menu.take() {
+            if let Some(name) = menu.pick(self.mouse_pos) {
+                self.accept_completion(start, &name);
+                return;
+            }
+        }
+        if let Some((cell, menu)) = self.context_menu.take() {
+            if let Some(a) = menu.pick(self.mouse_pos) {
+                self.output_action(cell, a);
+                return;
+            }
+        }
+        if self.show_outline {
+            let hit = self.outline_hits.iter().find(|&&(r, _)| r.contains(self.mouse_pos)).map(|&(_, i)| i);
+            if let Some(i) = hit {
+                self.reveal(i);
+                self.focus_cell(i, false);
+                return;
+            }
+        }
+        let bar = self.scrollbar(Rect::wh(self.view_w, self.view_h));
+        if let Some((track, _)) = bar {
+            if track.contains(self.mouse_pos) {
+                self.dragging_scrollbar = true;
+                let y = self.mouse_pos.y;
+                self.drag_scrollbar(y);
+                return;
+            }
+        }
+        let p = self.mouse_pos;
+        if let Some(c) = self.cell_at(p) {
+            if self.cells[c].surface.is_some() && self.cells[c].hit_plot(p) {
+                self.rotating = Some((c, p));
+                return;
+            }
+        }
+        let cell = match self.cell_at(p) {
+            Some(cell) => cell,
+            None => {
+                // below the last cell
+                let below = self.on_screen.last().map_or(false, |&i| p.y > self.cells[i].pos.y);
+                if below {
+                    let last = self.cells.len()-1;
+                    self.focus_cell(last, true);
+                }
+                return;
+            }
+        };
+        let shift = self.modifiers.shift;
+        if shift && cell != self.current_cell {
+            // shift-clicking another cell selects the cells between
+            self.cell_anchor = Some(self.cell_anchor.unwrap_or(self.current_cell));
+            self.current_cell = cell;
+            self.cursor = Cursor::new();
+            return;
+        }
+        self.cell_anchor = None;
+        if let Some(i) = self.cells[cell].hit_input(p) {
+            let now = Instant::now();
+            let double = cell == self.current_cell &&
+                self.last_click.map(|t| now.duration_since(t) < Duration::from_millis(400)).unwrap_or(false);
+            if cell != self.current_cell { self.focus_cell(cell, false); }
+            self.cursor.move_to(i, shift);
+            if double {
+                self.cursor.select_word(&self.cells[cell].input);
+                self.last_click = None;
+            } else {
+                self.dragging = true;
+                self.last_click = Some(now);
+            }
+            return;
+        }
+        if self.cells[cell].hit_prompt(p) && self.cells[cell].kind.heading_level().is_some() {
+            self.cells[cell].toggle_collapsed();
+            self.outline = None;
+            return;
+        }
+        let chain = self.cells[cell].hit_output(p);
+        if !chain.is_empty() {
+            self.select_subexpression(cell, chain);
+        }
+        if cell != self.current_cell { self.focus_cell(cell, true); }
+    }
+
+    /// the on-screen cell under the window point `p`
+    fn cell_at(&self, p: Point) -> Option<usize> {
+        self.on_screen.iter().cloned().find(|&i| {
+            let c = &self.cells[i];
+            p.y >= c.pos.y && p.y < c.pos.y + c.height() + 4.0
+        })
+    }
+
+    /// select the innermost subexpression in `chain`, or, when clicking again inside the current
+    /// selection, the smallest one enclosing it
+    fn select_subexpression(&mut self, cell: usize, chain: Vec<(Vec<usize>, Rect)>) {
+        let current = self.cells[cell].output_selection.as_ref()
+            .and_then(|sel| chain.iter().position(|&(ref path, _)| path == sel));
+        let pick = match current {
+            Some(k) => {
+                let r = chain[k].1;
+                chain[..k].iter().rposition(|&(_, pr)| pr.w > r.w || pr.h > r.h).unwrap_or(k)
+            },
+            None => chain.len()-1
+        };
+        self.set_output_selection(cell, chain[pick].0.clone());
+    }
+
+    /// select a subexpression of one cell's output, dropping any selection in the others
+    fn set_output_selection(&mut self, cell: usize, path: Vec<usize>) {
+        for c in self.cells.iter_mut() {
+            c.output_selection = None;
+        }
+        self.cells[cell].output_selection = Some(path);
+    }
+
+    /// the innermost output subexpression under the window point `p`
+    fn output_under(&self, p: Point) -> Option<(usize, Vec<usize>)> {
+        self.cell_at(p).and_then(|cell| self.cells[cell].hit_output(p).pop().map(|(path, _)| (cell, path)))
+    }
+
+    /// open the context menu for the subexpression under the mouse, keeping the current
+    /// selection if the mouse is inside it
+    fn open_context_menu(&mut self) {
+        let p = self.mouse_pos;
+        let cell = match self.cell_at(p) { Some(c) => c, None => return };
+        if self.cells[cell].hit_plot(p) {
+            let items = vec![
+                (String::from("Save plot as SVG"), OutputAction::SavePlot),
+                (String::from("Copy plot as SVG"), OutputAction::CopyPlot)
+            ];
+            self.context_menu = Some((cell, Menu::new(p, items)));
+            return;
+        }
+        let chain = self.cells[cell].hit_output(p);
+        let keep = self.cells[cell].output_selection.as_ref()
+            .map_or(false, |sel| chain.iter().any(|&(ref path, _)| path == sel));
+        if !keep {
+            match chain.last() {
+                Some(&(ref path, _)) => self.set_output_selection(cell, path.clone()),
+                None => return
+            }
+        }
+        // only offer to copy or reuse the subexpression as input if it can be written as input
+        let input = self.cells[cell].selected_output().and_then(|e| e.input_form()).is_some();
+        let mut items = Vec::new();
+        if input {
+            items.push((String::from("Copy"), OutputAction::Copy));
+        }
+        items.push((String::from("Copy LaTeX"), OutputAction::CopyLatex));
+        if input {
+            for f in &["factor", "expand", "ratsimp", "trigsimp", "float"] {
+                items.push((format!("{}(…)", f), OutputAction::Feed(*f)));
+            }
+        }
+        self.context_menu = Some((cell, Menu::new(p, items)));
+    }
+
+    fn output_action(&mut self, cell: usize, a: OutputAction) {
+        match a {
+            OutputAction::SavePlot => return self.save_plot(cell),
+            OutputAction::CopyPlot => {
+                let svg = self.cells[cell].plot.as_ref().map(|f| f.to_svg());
+                if let Some(svg) = svg { self.set_clipboard(svg); }
+                return;
+            },
+            _ => {}
+        }
+        let (input, tex) = match self.cells[cell].selected_output() {
+            Some(e) => (e.input_form(), latex::from_element(e)),
+            None => return
+        };
+        match (a, input) {
+            (OutputAction::CopyLatex, _) => self.set_clipboard(tex),
+            (OutputAction::Copy, Some(input)) => self.set_clipboard(input),
+            (OutputAction::Feed(f), Some(input)) => {
+                let text = format!("{}({});", f, input);
+                let len = text.len();
+                self.edit(CellOp::Insert { at: cell+1, cell: Cell::with_input(text) });
+                self.cursor.pos = len;
+            },
+            _ => {}
+        }
+    }
+
+    /// write a cell's plot next to the notebook, or in the working directory if there isn't one,
+    /// named after the cell's output label
+    fn save_plot(&mut self, cell: usize) {
+        let svg = match self.cells[cell].plot { Some(ref f) => f.to_svg(), None => return };
+        let name = match self.cells[cell].index {
+            Some(i) => format!("plot-o{}.svg", i),
+            None => String::from("plot.svg")
+        };
+        let dir = self.notebook_path.as_ref().and_then(|p| p.parent()).map(PathBuf::from).unwrap_or(PathBuf::from("."));
+        let path = dir.join(name);
+        match File::create(&path).and_then(|mut f| f.write_all(svg.as_bytes())) {
+            Ok(()) => println!("saved plot to {}", path.display()),
+            Err(e) => println!("error saving plot: {}", e)
+        }
+    }
+
+    fn mouse_moved(&mut self, p: Point) {
+        self.mouse_pos = p;
+        if let Some((cell, last)) = self.rotating {
+            self.cells[cell].view_surface(|s| s.rotate(p.x - last.x, p.y - last.y));
+            self.rotating = Some((cell, p));
+        } else if self.dragging_scrollbar {
+            self.drag_scrollbar(p.y);
+        } else if self.dragging && self.on_screen.contains(&self.current_cell) {
+            if let Some(i) = self.cells[self.current_cell].hit_input(p) {
+                self.cursor.move_to(i, true);
             }
         }
     }
@@ -157,34 +1134,146 @@ impl App for MaximaApp {
     fn paint(&mut self, rx: &mut RenderContext) {
         let bnds = rx.bounds();
         self.update(rx);
+        self.view_w = bnds.w;
+        self.view_h = bnds.h;
         rx.clear(Color::rgb(0.0, 0.0, 0.0));
         rx.set_color(Color::rgb(0.8, 0.75, 0.7));
-        let mut p = Point::xy(8.0, 8.0);
-        let fnt = self.font.clone();
-        let math_fnt = self.math_font.clone();
-        for (i, c) in self.cells.iter_mut().enumerate().skip(self.viewport_start) {
-            c.draw(p, rx, &fnt, &math_fnt);
+        let fonts = self.fonts.clone();
+        let visible = notebook::visibility(&self.cells);
+        let marked = if self.cell_anchor.is_some() { self.selected_cells() } else { 0..0 };
+
+        // place every cell by the height it had last time it was drawn, so that only the ones on screen need laying out
+        let mut tops = Vec::with_capacity(self.cells.len());
+        let mut y = 8.0;
+        for (i, c) in self.cells.iter().enumerate() {
+            tops.push(y);
+            if visible[i] { y += c.height() + 4.0; }
+        }
+        self.content_h = y + 8.0;
+        if self.scroll_to_current {
+            self.scroll_to_current = false;
+            if let Some(&top) = tops.get(self.current_cell) {
+                let h = self.cells[self.current_cell].height();
+                if top < self.scroll {
+                    self.scroll = top - 8.0;
+                } else if top + h > self.scroll + bnds.h {
+                    self.scroll = top + h + 8.0 - bnds.h;
+                }
+            }
+        }
+        self.scroll_by(0.0);
+
+        // cells whose height changed when drawn push the ones below them along
+        let mut shift = 0.0;
+        self.on_screen.clear();
+        for (i, c) in self.cells.iter_mut().enumerate() {
+            if !visible[i] { continue; }
+            let top = tops[i] + shift - self.scroll;
+            if top > bnds.h { break; }
+            let old_h = c.height();
+            if top + old_h < 0.0 { continue; }
+            let p = Point::xy(8.0, top);
+            c.draw(p, bnds.w - 26.0, rx, &fonts, &self.palette);
+            self.on_screen.push(i);
             let b = c.bounds();
+            shift += b.h - old_h;
+            if i >= marked.start && i < marked.end {
+                rx.set_color(Color::rgba(0.4, 0.4, 0.6, 0.8));
+                rx.fill_rect(Rect::xywh(2.0, p.y, 3.0, b.h));
+                rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+            }
             if i == self.current_cell {
-                c.draw_cursor(p, rx, self.cursor_idx);
+                c.draw_cursor(rx, &self.cursor);
             }
-            p.y += b.h + 4.0;
-            if p.y > bnds.h {
-                self.viewport_start += 1;
-                break;
+        }
+        if let Some((track, thumb)) = self.scrollbar(bnds) {
+            rx.set_color(Color::rgba(0.3, 0.3, 0.3, 0.5));
+            rx.fill_rect(track);
+            rx.set_color(Color::rgba(0.6, 0.6, 0.6, 0.8));
+            rx.fill_rect(thumb);
+            rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+        }
+        let hover = if self.context_menu.is_none() { self.output_under(self.mouse_pos) } else { None };
+        if let Some(r) = hover.and_then(|(cell, path)| self.cells[cell].output_rect(&path)) {
+            rx.set_color(Color::rgba(0.6, 0.6, 0.8, 0.6));
+            rx.stroke_rect(r, 1.0);
+            rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+        }
+        if self.show_outline {
+            self.draw_outline(rx);
+        }
+        if self.show_inspector {
+            self.inspector.draw(rx, &fonts);
+        }
+        if let Some(ref mut h) = self.help {
+            h.draw(rx, &fonts);
+        }
+        if self.search.is_some() {
+            self.draw_search(rx);
+        }
+        if let Some((_, ref mut menu)) = self.context_menu {
+            menu.draw(rx, &fonts.text, self.mouse_pos);
+        }
+        if let Some((cell, start, ref mut menu)) = self.completion_menu {
+            if self.on_screen.contains(&cell) {
+                menu.pos = self.cells[cell].caret_point(start);
+                menu.draw(rx, &fonts.code, self.mouse_pos);
             }
         }
     }
 
     fn event(&mut self, e: Event) -> bool {
         let cell = self.current_cell;
+        if let Event::WindowEvent { event: WindowEvent::KeyboardInput { ref input, .. }, .. } = e {
+            self.modifiers = input.modifiers;
+        }
         match e {
             Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. } => {
-                if !c.is_control() { 
-                    let cc = self.cursor_idx;
-                    self.cells[cell].input.insert(cc, c);
-                    self.cells[cell].input_layout = None;
-                    self.cursor_idx += 1;
+                // Alt chords are cell commands, and shouldn't also type their letter
+                if !c.is_control() && !self.modifiers.alt && self.search.is_some() {
+                    if let Some((ref mut query, _)) = self.search { query.push(c); }
+                    self.find_input(false);
+                } else if !c.is_control() && !self.modifiers.alt {
+                    if self.cells[cell].kind == CellKind::Code {
+                        self.text_edit(|cur, t| cur.type_char(t, c));
+                    } else {
+                        let mut buf = [0; 4];
+                        let s = c.encode_utf8(&mut buf);
+                        self.text_edit(|c, t| c.insert(t, s));
+                    }
+                    self.refresh_completions();
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position: (x, y), .. }, .. } => {
+                self.mouse_moved(Point::xy(x as f32, y as f32));
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button: MouseButton::Left, .. }, .. } => {
+                match state {
+                    ElementState::Pressed => self.mouse_down(),
+                    ElementState::Released => {
+                        self.dragging = false;
+                        self.dragging_scrollbar = false;
+                        self.rotating = None;
+                    }
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Right, .. }, .. } => {
+                self.open_context_menu();
+            },
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                let dy = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => -y * 48.0,
+                    MouseScrollDelta::PixelDelta(_, y) => -y
+                };
+                let bnds = Rect::wh(self.view_w, self.view_h);
+                let surface = self.cell_at(self.mouse_pos)
+                    .filter(|&c| self.cells[c].surface.is_some() && self.cells[c].hit_plot(self.mouse_pos));
+                match self.help {
+                    Some(ref mut h) if h.rect(bnds).contains(self.mouse_pos) => h.scroll_by(dy, bnds.h),
+                    _ if self.show_inspector && self.inspector.rect(bnds).contains(self.mouse_pos) => self.inspector.scroll_by(dy, bnds.h),
+                    // the wheel zooms a 3D plot rather than scrolling past it
+                    _ if surface.is_some() => self.cells[surface.unwrap()].view_surface(|s| s.zoom_by((-dy * 0.002).exp())),
+                    _ => self.scroll_by(dy)
                 }
             },
             Event::WindowEvent {
@@ -196,30 +1285,127 @@ impl App for MaximaApp {
                     }
                            , ..
                 }, .. } => {
+                    if mods.alt {
+                        self.cell_command(k, mods);
+                        return false;
+                    }
+                    if self.search.is_some() && self.search_key(k, mods) {
+                        return false;
+                    }
+                    if self.completion_menu.is_some() && k == VirtualKeyCode::Escape {
+                        self.completion_menu = None;
+                        return false;
+                    }
+                    let chosen = match self.completion_menu {
+                        Some((_, start, ref mut menu)) => match k {
+                            VirtualKeyCode::Up => { menu.select(-1); return false; },
+                            VirtualKeyCode::Down => { menu.select(1); return false; },
+                            // Shift+Enter and Ctrl+Enter still evaluate, closing the popup below
+                            VirtualKeyCode::Return | VirtualKeyCode::Tab if !mods.shift && !mods.ctrl =>
+                                menu.chosen().map(|name| (start, name)),
+                            _ => None
+                        },
+                        None => None
+                    };
+                    if let Some((start, name)) = chosen {
+                        self.completion_menu = None;
+                        self.accept_completion(start, &name);
+                        return false;
+                    }
                     match k {
-                        VirtualKeyCode::Return => {
+                        VirtualKeyCode::Left | VirtualKeyCode::Right | VirtualKeyCode::Home |
+                        VirtualKeyCode::End | VirtualKeyCode::Return => self.completion_menu = None,
+                        _ => {}
+                    }
+                    // text cells take formatting keys, where code cells copy the output
+                    let text = self.cells[cell].kind == CellKind::Text;
+                    match k {
+                        VirtualKeyCode::Z if mods.ctrl => self.undo(mods.shift),
+                        VirtualKeyCode::Tab => self.complete(),
+                        VirtualKeyCode::Up if mods.ctrl => self.recall(true),
+                        VirtualKeyCode::Down if mods.ctrl => self.recall(false),
+                        VirtualKeyCode::R if mods.ctrl => {
+                            self.search = Some((String::new(), None));
+                            self.search_layout = None;
+                        },
+                        VirtualKeyCode::F1 => self.lookup_help(),
+                        VirtualKeyCode::F3 => self.toggle_inspector(),
+                        VirtualKeyCode::Escape if self.help.is_some() => self.help = None,
+                        VirtualKeyCode::S if mods.ctrl => self.save(),
+                        VirtualKeyCode::O if mods.ctrl => self.open(),
+                        VirtualKeyCode::F2 => self.show_outline = !self.show_outline,
+                        VirtualKeyCode::F5 => {
+                            let n = self.cells.len();
                             if mods.shift {
-                                self.cells[cell].input.push(';');
-                                self.cells[cell].input_layout = None;
+                                self.evaluate_range(cell..n);
+                            } else if mods.ctrl {
+                                let r = self.selected_cells();
+                                self.evaluate_range(r);
+                            } else {
+                                self.evaluate_range(0..n);
                             }
-                            write!(self.maxima_strm, "{}", self.cells[cell].input).expect("write stream");
                         }
-                        VirtualKeyCode::Left => { if self.cursor_idx > 0 { self.cursor_idx -= 1; } }
-                        VirtualKeyCode::Right => {
-                            let len = self.cells[cell].input.len();
-                            if self.cursor_idx < len { self.cursor_idx += 1; }
+                        VirtualKeyCode::Escape if self.context_menu.is_some() => self.context_menu = None,
+                        VirtualKeyCode::Escape => {
+                            self.cancel_queue();
+                            self.cell_anchor = None;
                         }
-                        VirtualKeyCode::Back => {
-                            if self.cursor_idx > 0 && self.cells[cell].input.len() != 0 {
-                                self.cursor_idx -= 1;
-                                self.cells[cell].input.remove(self.cursor_idx);
-                                self.cells[cell].input_layout = None;
+                        VirtualKeyCode::Return => {
+                            if mods.shift || mods.ctrl {
+                                self.evaluate(cell);
+                            } else {
+                                if self.cells[cell].kind == CellKind::Code {
+                                    self.text_edit(|c, t| c.newline(t));
+                                } else {
+                                    self.text_edit(|c, t| c.insert(t, "\n"));
+                                }
+                            }
+                        }
+                        VirtualKeyCode::Left => self.cursor.left(&self.cells[cell].input, mods.shift),
+                        VirtualKeyCode::Right => self.cursor.right(&self.cells[cell].input, mods.shift),
+                        VirtualKeyCode::Up => {
+                            match self.cells[cell].vertical_move(self.cursor.pos, -1.0) {
+                                Some(p) => self.cursor.move_to(p, mods.shift),
+                                None if !mods.shift && self.neighbour(cell, false).is_some() => {
+                                    let n = self.neighbour(cell, false).unwrap();
+                                    self.focus_cell(n, true)
+                                },
+                                None => self.cursor.move_to(0, mods.shift)
+                            }
+                        }
+                        VirtualKeyCode::Down => {
+                            match self.cells[cell].vertical_move(self.cursor.pos, 1.0) {
+                                Some(p) => self.cursor.move_to(p, mods.shift),
+                                None if !mods.shift && self.neighbour(cell, true).is_some() => {
+                                    let n = self.neighbour(cell, true).unwrap();
+                                    self.focus_cell(n, false)
+                                },
+                                None => {
+                                    let len = self.cells[cell].input.len();
+                                    self.cursor.move_to(len, mods.shift)
+                                }
                             }
                         }
-                        VirtualKeyCode::PageUp => { if self.viewport_start > 0 { self.viewport_start -= 1; } }
-                        VirtualKeyCode::PageDown => { if self.viewport_start < self.cells.len() { self.viewport_start += 1; } }
-                        /*VirtualKeyCode::Up => { if self.current_cell > 0 { self.current_cell -= 1; } }
-                          VirtualKeyCode::Down => { if self.current_cell < self.cells.len() { self.current_cell += 1; } }*/
+                        VirtualKeyCode::Home => self.cursor.home(&self.cells[cell].input, mods.shift),
+                        VirtualKeyCode::End => self.cursor.end(&self.cells[cell].input, mods.shift),
+                        VirtualKeyCode::Back => {
+                            let code = self.cells[cell].kind == CellKind::Code;
+                            self.text_edit(|c, t| if !(code && c.backspace_pair(t)) { c.backspace(t) });
+                            self.refresh_completions();
+                        }
+                        VirtualKeyCode::A if mods.ctrl => self.cursor.select_all(&self.cells[cell].input),
+                        VirtualKeyCode::C if mods.ctrl => self.copy_input(),
+                        VirtualKeyCode::X if mods.ctrl => self.cut_input(),
+                        VirtualKeyCode::V if mods.ctrl => self.paste(),
+                        VirtualKeyCode::L if mods.ctrl => self.copy_latex(mods.shift),
+                        VirtualKeyCode::B if mods.ctrl && text => self.text_edit(|c, t| c.toggle_marker(t, "**")),
+                        VirtualKeyCode::I if mods.ctrl && text => self.text_edit(|c, t| c.toggle_marker(t, "*")),
+                        VirtualKeyCode::E if mods.ctrl && text => self.text_edit(|c, t| c.toggle_marker(t, "`")),
+                        VirtualKeyCode::I if mods.ctrl => self.copy_linear(),
+                        VirtualKeyCode::M if mods.ctrl => self.copy_mathml(),
+                        VirtualKeyCode::T if mods.ctrl => self.copy_text(),
+                        VirtualKeyCode::PageUp => { let d = self.view_h * 0.9; self.scroll_by(-d); }
+                        VirtualKeyCode::PageDown => { let d = self.view_h * 0.9; self.scroll_by(d); }
                         _ => {}
                     }
                 }
@@ -232,6 +1418,11 @@ impl App for MaximaApp {
 impl Drop for MaximaApp {
     fn drop(&mut self) {
         self.maxima_proc.kill().expect("end maxima client!");
+        if let Some(dir) = self.plot_file.parent() {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                println!("error removing plot directory: {}", e);
+            }
+        }
     }
 }
 
@@ -240,6 +1431,16 @@ fn main() -> Result<(), Box<Error>> {
     let mut evl = EventsLoop::new();
     let mut window = WindowBuilder::new().with_dimensions(640, 400).with_title("rMaxima").build(&evl)?;
     let mut rx = RenderContext::new(&mut window)?;
-    let mut app = MaximaApp::new(&mut rx)?;
+    // also have Maxima render each output as TeX, for comparing with our own conversion
+    let request_tex = std::env::args().any(|a| a == "--tex");
+    let notebook_path = std::env::args().skip(1).find(|a| !a.starts_with("--")).map(PathBuf::from);
+    let stop_on_error = std::env::args().any(|a| a == "--stop-on-error");
+    // draw plots with gnuplot rather than from points Maxima samples for us
+    let native_plots = !std::env::args().any(|a| a == "--gnuplot");
+    let palette = match std::env::args().find(|a| a.starts_with("--theme=")) {
+        Some(a) => Palette::load(&a["--theme=".len()..])?,
+        None => Palette::dark()
+    };
+    let mut app = MaximaApp::new(&mut rx, request_tex, native_plots, stop_on_error, notebook_path, palette)?;
     Ok(app.run(&mut rx, &mut evl))
 }
@@ -0,0 +1,127 @@
+use std::ops::Range;
+
+/// How a piece of a text cell is set
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Style {
+    Bold, Italic,
+    /// Maxima code, highlighted as it would be in a code cell
+    Code
+}
+
+impl Style {
+    /// what the text is written between
+    pub fn marker(&self) -> &'static str {
+        match *self {
+            Style::Bold => "**",
+            Style::Italic => "*",
+            Style::Code => "`"
+        }
+    }
+}
+
+/// A formatted piece of a text cell
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub style: Style,
+    /// byte range in the cell's text, markers included
+    pub range: Range<usize>
+}
+
+impl Span {
+    /// the text between the markers
+    pub fn inner(&self) -> Range<usize> {
+        let m = self.style.marker().len();
+        self.range.start + m..self.range.end - m
+    }
+}
+
+/// Find the formatting in a text cell, which is written as `**bold**`, `*italic*` and `` `code` ``.
+/// A star only opens a span if text follows it directly and only closes one if text directly
+/// precedes it, so that `2 * 3` stays as it is. Nothing inside a code span is formatted, and
+/// markers that are never closed are plain text
+pub fn parse(text: &str) -> Vec<Span> {
+    const STYLES: [Style; 2] = [Style::Bold, Style::Italic];
+    let mut spans = Vec::new();
+    // where the bold and the italic span being read started
+    let mut open: [Option<usize>; 2] = [None, None];
+    let b = text.as_bytes();
+    let mut i = 0;
+    while i < b.len() {
+        match b[i] {
+            b'`' => match text[i+1..].find('`') {
+                Some(n) if n > 0 => {
+                    spans.push(Span { style: Style::Code, range: i..i+n+2 });
+                    i += n + 2;
+                },
+                _ => i += 1
+            },
+            b'*' => {
+                // a run of stars can close one span and open or close another, as in `***`
+                let end = i + b[i..].iter().take_while(|&&c| c == b'*').count();
+                let before = text[..i].chars().next_back().map_or(false, |c| !c.is_whitespace());
+                let after = text[end..].chars().next().map_or(false, |c| !c.is_whitespace());
+                let mut at = i;
+                while at < end {
+                    let left = end - at;
+                    // the span opened last is closed first, so that spans nest
+                    let closing = (0..2).filter(|&k| {
+                        let len = STYLES[k].marker().len();
+                        left >= len && open[k].map_or(false, |s| at > s + len)
+                    }).max_by_key(|&k| open[k]);
+                    let k = match closing {
+                        Some(k) if before => {
+                            let len = STYLES[k].marker().len();
+                            spans.push(Span { style: STYLES[k], range: open[k].unwrap()..at+len });
+                            open[k] = None;
+                            at += len;
+                            continue;
+                        },
+                        _ if !after => break,
+                        _ if left >= 2 && open[0].is_none() => 0,
+                        _ if open[1].is_none() => 1,
+                        _ => break
+                    };
+                    open[k] = Some(at);
+                    at += STYLES[k].marker().len();
+                }
+                i = end;
+            },
+            _ => i += 1
+        }
+    }
+    spans.sort_by_key(|s| s.range.start);
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Span, Style};
+
+    fn spans(text: &str) -> Vec<(Style, &str)> {
+        parse(text).iter().map(|s: &Span| (s.style, &text[s.inner()])).collect()
+    }
+
+    #[test]
+    fn styles() {
+        assert_eq!(spans("a **bold** and *slanted* `x^2`"),
+                   vec![(Style::Bold, "bold"), (Style::Italic, "slanted"), (Style::Code, "x^2")]);
+        assert_eq!(parse("**b**")[0].range, 0..5);
+    }
+
+    #[test]
+    fn nesting() {
+        assert_eq!(spans("**bold *and slanted***"), vec![(Style::Bold, "bold *and slanted*"), (Style::Italic, "and slanted")]);
+        assert_eq!(spans("***x***"), vec![(Style::Bold, "*x*"), (Style::Italic, "x")]);
+        // nothing inside code is formatted
+        assert_eq!(spans("`a*b*c` *d*"), vec![(Style::Code, "a*b*c"), (Style::Italic, "d")]);
+    }
+
+    #[test]
+    fn plain() {
+        assert!(parse("2 * 3 * 4").is_empty());
+        assert!(parse("an *unclosed span").is_empty());
+        assert!(parse("empty ** and ``").is_empty());
+        assert!(parse("a lone ` tick").is_empty());
+        assert!(parse("****").is_empty());
+    }
+}
@@ -60,13 +60,318 @@ pub enum Element {
     Fenced { open: String, close: String, seperator: String, children: Vec<Element> },
     Subscript { base: Box<Element>, script: Box<Element> },
     Superscript { base: Box<Element>, script: Box<Element> },
-    Subsuperscript { base: Box<Element>, subscript: Box<Element>, superscript: Box<Element> }
+    Subsuperscript { base: Box<Element>, subscript: Box<Element>, superscript: Box<Element> },
+    Under { base: Box<Element>, script: Box<Element> },
+    Over { base: Box<Element>, script: Box<Element> },
+    UnderOver { base: Box<Element>, under: Box<Element>, over: Box<Element> },
+    Table(Vec<Vec<Element>>)
 }
 
 fn union_rect(a: Rect, b: Rect) -> Rect {
     Rect::xywh(a.x.min(b.x), a.y.min(b.y), a.w.max(b.w), a.h.max(b.h))
 }
 
+/// column widths and row heights of a table, measured over every cell
+fn table_metrics(rows: &Vec<Vec<Element>>) -> (Vec<f32>, Vec<f32>) {
+    let mut cols: Vec<f32> = Vec::new();
+    let mut heights = Vec::new();
+    for row in rows {
+        let mut h = 0f32;
+        for (i, b) in row.iter().map(|e| e.bounds()).enumerate() {
+            if i >= cols.len() { cols.push(0.0); }
+            cols[i] = cols[i].max(b.w);
+            h = h.max(b.h);
+        }
+        heights.push(h);
+    }
+    (cols, heights)
+}
+
+/// Which kind of token a leaf of an expression is
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Leaf {
+    Id, Number, Operator
+}
+
+/// A sum, product, limit or integral sign, which applies to the rest of the row it starts, with
+/// the limits set under and over it
+pub struct BigOperator<'a> {
+    pub symbol: &'a str,
+    pub under: Option<&'a Element>,
+    pub over: Option<&'a Element>
+}
+
+/// A way of writing an expression as a line of text, used by `Element::write`. The defaults give
+/// the plain text form, with parentheses wherever the 2D layout implied grouping. Each method
+/// gives None if the notation can't write that construct
+pub trait Notation: Sized {
+    fn leaf(&self, kind: Leaf, s: &str, out: &mut String) -> Option<()>;
+
+    fn space(&self, out: &mut String) -> Option<()> {
+        out.push(' ');
+        Some(())
+    }
+
+    /// write `e` so that it reads as one unit, as the parts of a fraction or a power must
+    fn group(&self, e: &Element, out: &mut String) -> Option<()> {
+        if e.is_leaf() { return e.write(self, out); }
+        out.push('(');
+        e.write(self, out)?;
+        out.push(')');
+        Some(())
+    }
+
+    fn fraction(&self, numer: &Element, denom: &Element, out: &mut String) -> Option<()> {
+        self.group(numer, out)?;
+        out.push('/');
+        self.group(denom, out)
+    }
+
+    /// a square root, or a root with an index
+    fn root(&self, base: &Element, index: Option<&Element>, out: &mut String) -> Option<()> {
+        match index {
+            None => { out.push('√'); self.group(base, out) },
+            Some(i) => {
+                self.group(base, out)?;
+                out.push_str("^(1/");
+                i.write(self, out)?;
+                out.push(')');
+                Some(())
+            }
+        }
+    }
+
+    fn fenced(&self, open: &str, close: &str, sep: &str, children: &[Element], out: &mut String) -> Option<()> {
+        out.push_str(open);
+        for (i, c) in children.iter().enumerate() {
+            if i > 0 { out.push_str(sep); out.push(' '); }
+            c.write(self, out)?;
+        }
+        out.push_str(close);
+        Some(())
+    }
+
+    /// a base with a subscript, a superscript or both
+    fn scripts(&self, base: &Element, sub: Option<&Element>, sup: Option<&Element>, out: &mut String) -> Option<()> {
+        base.write(self, out)?;
+        if let Some(s) = sub { out.push('_'); self.group(s, out)?; }
+        if let Some(s) = sup { out.push('^'); self.group(s, out)?; }
+        Some(())
+    }
+
+    /// a base with something set under it, over it or both
+    fn stacked(&self, base: &Element, under: Option<&Element>, over: Option<&Element>, out: &mut String) -> Option<()> {
+        self.scripts(base, under, over, out)
+    }
+
+    fn table(&self, rows: &[Vec<Element>], out: &mut String) -> Option<()> {
+        out.push('[');
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 { out.push_str(", "); }
+            out.push('[');
+            for (j, e) in row.iter().enumerate() {
+                if j > 0 { out.push_str(", "); }
+                e.write(self, out)?;
+            }
+            out.push(']');
+        }
+        out.push(']');
+        Some(())
+    }
+
+    /// `sign`, which is the operator `op`, followed by what it applies to
+    fn big_operator(&self, sign: &Element, _op: BigOperator, body: &[Element], out: &mut String) -> Option<()> {
+        sign.write(self, out)?;
+        for e in body { e.write(self, out)?; }
+        Some(())
+    }
+}
+
+/// The expression as it reads, for copying as text
+pub struct PlainText;
+
+impl Notation for PlainText {
+    fn leaf(&self, _: Leaf, s: &str, out: &mut String) -> Option<()> {
+        out.push_str(s);
+        Some(())
+    }
+}
+
+/// Maxima input syntax, so that a piece of output can be typed back in
+pub struct InputForm;
+
+impl InputForm {
+    fn write_all(&self, els: &[Element], out: &mut String) -> Option<()> {
+        for e in els { e.write(self, out)?; }
+        Some(())
+    }
+
+    /// the parts of a row on either side of the operator `op`, as in the `k = 1` under a sum
+    fn split<'a>(e: &'a Element, op: &str) -> Option<(&'a [Element], &'a [Element])> {
+        match e {
+            &Element::Row(ref els) => {
+                let i = els.iter().position(|e| e.is_leaf() && e.leaf_text() == Some(op))?;
+                Some((&els[..i], &els[i+1..]))
+            },
+            _ => None
+        }
+    }
+
+    /// an integrand and the variable after the `d` that ends it
+    fn differential(body: &[Element]) -> Option<(&[Element], &[Element])> {
+        let is_d = |e: &Element| e.leaf_text().map_or(false, |s| s == "𝑑" || s == "d");
+        // the end of `els`, leaving off any space after it
+        let trim = |els: &[Element]| els.iter().rposition(|e| !e.is_space()).map_or(0, |i| i+1);
+        let end = trim(body).checked_sub(1)?;
+        if let Element::Row(ref els) = body[end] {
+            let first = els.iter().position(|e| !e.is_space())?;
+            if is_d(&els[first]) { return Some((&body[..trim(&body[..end])], &els[first+1..])); }
+        }
+        let d = trim(&body[..end]).checked_sub(1)?;
+        if is_d(&body[d]) { Some((&body[..trim(&body[..d])], &body[end..end+1])) } else { None }
+    }
+}
+
+impl Notation for InputForm {
+    fn leaf(&self, _: Leaf, s: &str, out: &mut String) -> Option<()> {
+        match s {
+            "𝜋" | "π" => out.push_str("%pi"),
+            "𝒆" => out.push_str("%e"),
+            "𝑖" => out.push_str("%i"),
+            "∞" => out.push_str("inf"),
+            "·" | "⋅" | "×" | "\u{2062}" => out.push('*'),
+            "−" => out.push('-'),
+            "\u{2061}" => {},
+            _ => out.push_str(s)
+        }
+        Some(())
+    }
+
+    fn group(&self, e: &Element, out: &mut String) -> Option<()> {
+        match e {
+            &Element::Id(..) | &Element::Number(..) => e.write(self, out),
+            _ => { out.push('('); e.write(self, out)?; out.push(')'); Some(()) }
+        }
+    }
+
+    fn root(&self, base: &Element, index: Option<&Element>, out: &mut String) -> Option<()> {
+        match index {
+            None => { out.push_str("sqrt("); base.write(self, out)?; out.push(')'); },
+            Some(i) => {
+                self.group(base, out)?;
+                out.push_str("^(1/");
+                self.group(i, out)?;
+                out.push(')');
+            }
+        }
+        Some(())
+    }
+
+    fn fenced(&self, open: &str, close: &str, _: &str, children: &[Element], out: &mut String) -> Option<()> {
+        if children.len() == 1 && children[0].is_table() {
+            return children[0].write(self, out);
+        }
+        out.push_str(if open.is_empty() { "(" } else { open });
+        for (i, c) in children.iter().enumerate() {
+            if i > 0 { out.push_str(", "); }
+            c.write(self, out)?;
+        }
+        out.push_str(if close.is_empty() { ")" } else { close });
+        Some(())
+    }
+
+    fn scripts(&self, base: &Element, sub: Option<&Element>, sup: Option<&Element>, out: &mut String) -> Option<()> {
+        match (sub, sup) {
+            (Some(s), None) => {
+                base.write(self, out)?;
+                out.push('[');
+                s.write(self, out)?;
+                out.push(']');
+            },
+            (None, Some(s)) => {
+                self.group(base, out)?;
+                out.push('^');
+                self.group(s, out)?;
+            },
+            (Some(sub), Some(sup)) => {
+                out.push('(');
+                base.write(self, out)?;
+                out.push('[');
+                sub.write(self, out)?;
+                out.push_str("])^");
+                self.group(sup, out)?;
+            },
+            (None, None) => base.write(self, out)?
+        }
+        Some(())
+    }
+
+    /// anything stacked that isn't a sum or the like has no input form
+    fn stacked(&self, _: &Element, _: Option<&Element>, _: Option<&Element>, _: &mut String) -> Option<()> {
+        None
+    }
+
+    fn table(&self, rows: &[Vec<Element>], out: &mut String) -> Option<()> {
+        out.push_str("matrix(");
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 { out.push_str(", "); }
+            out.push('[');
+            for (j, e) in row.iter().enumerate() {
+                if j > 0 { out.push_str(", "); }
+                e.write(self, out)?;
+            }
+            out.push(']');
+        }
+        out.push(')');
+        Some(())
+    }
+
+    /// `sum(body, k, 1, n)`, `product(...)`, `limit(body, x, 0)` or `integrate(body, x, a, b)`
+    fn big_operator(&self, _: &Element, op: BigOperator, body: &[Element], out: &mut String) -> Option<()> {
+        match op.symbol {
+            "∑" | "∏" => {
+                let (var, from) = InputForm::split(op.under?, "=")?;
+                out.push_str(if op.symbol == "∑" { "sum(" } else { "product(" });
+                self.write_all(body, out)?;
+                out.push_str(", ");
+                self.write_all(var, out)?;
+                out.push_str(", ");
+                self.write_all(from, out)?;
+                out.push_str(", ");
+                op.over?.write(self, out)?;
+            },
+            "lim" => {
+                let (var, to) = InputForm::split(op.under?, "→")?;
+                out.push_str("limit(");
+                self.write_all(body, out)?;
+                out.push_str(", ");
+                self.write_all(var, out)?;
+                out.push_str(", ");
+                self.write_all(to, out)?;
+            },
+            _ => {
+                let (integrand, var) = InputForm::differential(body)?;
+                out.push_str("integrate(");
+                self.write_all(integrand, out)?;
+                out.push_str(", ");
+                self.write_all(var, out)?;
+                match (op.under, op.over) {
+                    (Some(a), Some(b)) => {
+                        out.push_str(", ");
+                        a.write(self, out)?;
+                        out.push_str(", ");
+                        b.write(self, out)?;
+                    },
+                    (None, None) => {},
+                    _ => return None
+                }
+            }
+        }
+        out.push(')');
+        Some(())
+    }
+}
+
 impl Element {
     fn is_placeholder(&self) -> bool {
         match self {
@@ -80,6 +385,12 @@ impl Element {
             _ => false
         }
     }
+    fn is_table(&self) -> bool {
+        match self {
+            &Element::Table(_) => true,
+            _ => false
+        }
+    }
 
     fn set_body(&mut self, s: String, rx: &mut RenderContext, fnt: &Font) -> Result<(), MathMLParseError> {
         match self {
@@ -159,6 +470,38 @@ impl Element {
                 }
                 Ok(())
             },
+            &mut Element::Under { ref mut base, ref mut script } | Element::Over { ref mut base, ref mut script } => {
+                if base.is_placeholder() {
+                    *base = Box::new(e);
+                } else if script.is_placeholder() {
+                    *script = Box::new(e);
+                    script.add_script_level();
+                } else {
+                    return Err(MathMLParseError::AppendToFullNode("under/over"))
+                }
+                Ok(())
+            },
+            &mut Element::UnderOver { ref mut base, ref mut under, ref mut over } => {
+                if base.is_placeholder() {
+                    *base = Box::new(e);
+                } else if under.is_placeholder() {
+                    *under = Box::new(e);
+                    under.add_script_level();
+                } else if over.is_placeholder() {
+                    *over = Box::new(e);
+                    over.add_script_level();
+                } else {
+                    return Err(MathMLParseError::AppendToFullNode("underover"))
+                }
+                Ok(())
+            },
+            &mut Element::Table(ref mut rows) => {
+                // <mtr> parses as a row, and each <mtd> inside it as another row
+                match e {
+                    Element::Row(cells) => { rows.push(cells); Ok(()) },
+                    _ => Err(MathMLParseError::AppendToFullNode("table"))
+                }
+            },
             _ => Err(MathMLParseError::AppendToLeaf)
         }
     }
@@ -202,11 +545,99 @@ impl Element {
                 subscript.add_script_level();
                 superscript.add_script_level();
             }
+            &mut Element::Under { ref mut base, ref mut script } | &mut Element::Over { ref mut base, ref mut script } => {
+                base.add_script_level();
+                script.add_script_level();
+            }
+            &mut Element::UnderOver { ref mut base, ref mut under, ref mut over } => {
+                base.add_script_level();
+                under.add_script_level();
+                over.add_script_level();
+            }
+            &mut Element::Table(ref mut rows) => {
+                for e in rows.iter_mut().flat_map(|r| r.iter_mut()) {
+                    e.add_script_level();
+                }
+            }
             &mut Element::Space(size) => {}
             _ => panic!("bounds for silly element")
         }
     }
 
+    fn is_leaf(&self) -> bool {
+        match self {
+            &Element::Id(..) | &Element::Number(..) | &Element::Operator(..) => true,
+            _ => false
+        }
+    }
+
+    /// the text of a name, number or operator
+    fn leaf_text(&self) -> Option<&str> {
+        match self {
+            &Element::Id(ref s, ..) | &Element::Number(ref s, ..) | &Element::Operator(ref s, ..) => Some(s),
+            _ => None
+        }
+    }
+
+    /// the sum, product, limit or integral sign this element is, with whatever is set under and over it
+    fn big_operator(&self) -> Option<BigOperator> {
+        let (base, under, over) = match self {
+            &Element::Id(..) | &Element::Operator(..) => (self, None, None),
+            &Element::Under { ref base, ref script } | &Element::Subscript { ref base, ref script } => (&**base, Some(&**script), None),
+            &Element::Over { ref base, ref script } | &Element::Superscript { ref base, ref script } => (&**base, None, Some(&**script)),
+            &Element::UnderOver { ref base, ref under, ref over } |
+            &Element::Subsuperscript { ref base, subscript: ref under, superscript: ref over } => (&**base, Some(&**under), Some(&**over)),
+            _ => return None
+        };
+        match base.leaf_text()? {
+            // a bare integral sign is an indefinite integral, but the others need their limits
+            "∫" => Some(BigOperator { symbol: "∫", under, over }),
+            "∑" | "∏" | "lim" if under.is_some() => Some(BigOperator { symbol: base.leaf_text()?, under, over }),
+            _ => None
+        }
+    }
+
+    /// Write the element out as a line of text in some notation, or give None if the notation has
+    /// no way to write part of it. The tree is walked the same way for every notation, which
+    /// only decides how each construct is written
+    pub fn write<N: Notation>(&self, n: &N, out: &mut String) -> Option<()> {
+        match self {
+            &Element::ParsePlaceholder => Some(()),
+            &Element::Id(ref s, ..) => n.leaf(Leaf::Id, s, out),
+            &Element::Number(ref s, ..) => n.leaf(Leaf::Number, s, out),
+            &Element::Operator(ref s, ..) => n.leaf(Leaf::Operator, s, out),
+            &Element::Space(_) => n.space(out),
+            &Element::Row(ref els) => {
+                for (i, e) in els.iter().enumerate() {
+                    // a sum or the like applies to the rest of its row
+                    if let Some(op) = e.big_operator() {
+                        return n.big_operator(e, op, &els[i+1..], out);
+                    }
+                    e.write(n, out)?;
+                }
+                Some(())
+            },
+            &Element::Fraction { ref numer, ref denom, .. } => n.fraction(numer, denom, out),
+            &Element::Sqrt(ref c) => n.root(c, None, out),
+            &Element::Root { ref base, ref index } => n.root(base, Some(index), out),
+            &Element::Fenced { ref open, ref close, ref seperator, ref children } => n.fenced(open, close, seperator.trim(), children, out),
+            &Element::Subscript { ref base, ref script } => n.scripts(base, Some(script), None, out),
+            &Element::Superscript { ref base, ref script } => n.scripts(base, None, Some(script), out),
+            &Element::Subsuperscript { ref base, ref subscript, ref superscript } => n.scripts(base, Some(subscript), Some(superscript), out),
+            &Element::Under { ref base, ref script } => n.stacked(base, Some(script), None, out),
+            &Element::Over { ref base, ref script } => n.stacked(base, None, Some(script), out),
+            &Element::UnderOver { ref base, ref under, ref over } => n.stacked(base, Some(under), Some(over), out),
+            &Element::Table(ref rows) => n.table(rows, out)
+        }
+    }
+
+    /// the element as Maxima input, if it can be written as such
+    pub fn input_form(&self) -> Option<String> {
+        let mut s = String::new();
+        self.write(&InputForm, &mut s)?;
+        Some(s.trim().to_owned())
+    }
+
     fn from_mathml<R: Read>(reader: &mut EventReader<R>, rx: &mut RenderContext, fnt: &Font) -> Result<Element, MathMLParseError> {
         let mut els: Vec<Element> = Vec::new();
         loop {
@@ -237,6 +668,13 @@ impl Element {
                         "msubsup" => { Element::Subsuperscript { base: Box::new(Element::ParsePlaceholder),
                                                                 subscript: Box::new(Element::ParsePlaceholder),
                                                                 superscript: Box::new(Element::ParsePlaceholder) } },
+                        "munder" => { Element::Under { base: Box::new(Element::ParsePlaceholder), script: Box::new(Element::ParsePlaceholder) } },
+                        "mover" => { Element::Over { base: Box::new(Element::ParsePlaceholder), script: Box::new(Element::ParsePlaceholder) } },
+                        "munderover" => { Element::UnderOver { base: Box::new(Element::ParsePlaceholder),
+                                                                under: Box::new(Element::ParsePlaceholder),
+                                                                over: Box::new(Element::ParsePlaceholder) } },
+                        "mtable" => Element::Table(Vec::new()),
+                        "mtr" | "mtd" => Element::Row(Vec::new()),
                         _ => return Err(MathMLParseError::UnexpectedXMLTag(name.local_name))
                     });
                 }
@@ -327,6 +765,22 @@ impl Element {
                 bb.h += spb.h/2.0;
                 bb
             }
+            &Element::Under { ref base, ref script } | &Element::Over { ref base, ref script } => {
+                let bb = base.bounds();
+                let sb = script.bounds();
+                Rect::xywh(0.0, 0.0, bb.w.max(sb.w), bb.h + sb.h + 2.0)
+            }
+            &Element::UnderOver { ref base, ref under, ref over } => {
+                let bb = base.bounds();
+                let ub = under.bounds();
+                let ob = over.bounds();
+                Rect::xywh(0.0, 0.0, bb.w.max(ub.w).max(ob.w), bb.h + ub.h + ob.h + 4.0)
+            }
+            &Element::Table(ref rows) => {
+                let (cols, heights) = table_metrics(rows);
+                Rect::xywh(0.0, 0.0, cols.iter().map(|w| w + 12.0).sum(),
+                           heights.iter().map(|h| h + 4.0).sum())
+            }
             &Element::Space(size) => {
                 Rect::wh(0.0, 0.0)
             }
@@ -360,79 +814,151 @@ impl Element {
 
         //rx.stroke_rect(self.bounds().offset(p), 1.0);
 
+        // draw what the element adds around its children, then the children where they are placed
         match self {
             &Element::Id(_, ref ly, _) | &Element::Number(_, ref ly, _) | &Element::Operator(_, ref ly, _) => {
                 let ly = ly.as_ref().unwrap();
                 let b = ly.bounds();
                 rx.draw_text_layout(p - Point::xy(0.0, b.h/2.0), ly);
             },
-            &Element::Row(ref els) => {
-                let mut pp = p;
-                for e in els {
-                    e.draw(pp, rx);
-                    let eb = e.bounds();
-                    pp.x += eb.x+eb.w+2.0;
-                }
-            },
             &Element::Fraction { ref numer, ref denom, script } => {
                 let nb = numer.bounds();
                 let db = denom.bounds();
                 if script {
                     let h = nb.h.max(db.h);
-                    numer.draw(p, rx);
                     if nb.w > 30.0 { draw_fence(rx, p, nb.w, nb.h); }
                     rx.draw_line(p+Point::xy(nb.w+1.0, h/2.0), p+Point::xy(nb.w+4.0, -h/2.0), 1.0);
-                    denom.draw(p + Point::x(nb.w + 8.0), rx);
                     if db.w > 30.0 { draw_fence(rx, p + Point::x(nb.w + 8.0), db.w, db.h); }
                 }
                 else {
-                    numer.draw(p - Point::xy(0.0, nb.h / 2.0 + 1.0), rx);
                     rx.draw_line(p, p + Point::xy(nb.w.max(db.w), 0.0), 1.0);
-                    denom.draw(p + Point::xy(0.0, db.h / 2.0 + 1.0), rx);
                 }
             },
-            &Element::Sqrt(ref el) => {
-                let eb = el.bounds();
-                draw_radical(rx, p, eb, 2.0);
-                el.draw(p + Point::xy(9.0, 0.0), rx);
-            },
-            &Element::Root { ref base, ref index } => {
-                let eb = base.bounds();
-                let ib = index.bounds();
-                draw_radical(rx, p, eb, ib.w);
-                base.draw(p + Point::xy(ib.w+7.0, 0.0), rx);
-                index.draw(p - Point::xy(0.0,ib.h/2.0), rx);
-            },
+            &Element::Sqrt(ref el) => draw_radical(rx, p, el.bounds(), 2.0),
+            &Element::Root { ref base, ref index } => draw_radical(rx, p, base.bounds(), index.bounds().w),
             &Element::Fenced { ref children, .. } => {
+                let w = children.iter().map(|e| { let eb = e.bounds(); eb.x+eb.w+2.0 }).sum::<f32>();
+                let h = children.iter().map(|e| e.bounds().h).fold(0f32, f32::max);
+                draw_fence(rx, p, w, h);
+            },
+            &Element::ParsePlaceholder => panic!("draw silly element"),
+            _ => {}
+        }
+        for (c, cp) in self.placed_children(p) {
+            c.draw(cp, rx);
+        }
+    }
+
+    /// the children of this element and the points they get drawn at when it is drawn at `p`. This is
+    /// the one place children are laid out, for drawing and for finding what is under the mouse alike
+    fn placed_children(&self, p: Point) -> Vec<(&Element, Point)> {
+        match self {
+            &Element::Row(ref els) | &Element::Fenced { children: ref els, .. } => {
+                let inset = if self.is_row() { 0.0 } else { 2.0 };
                 let mut pp = p;
-                let mut h = 0f32;
-                for e in children {
-                    e.draw(pp + Point::x(2.0), rx);
+                els.iter().map(|e| {
+                    let at = pp + Point::x(inset);
                     let eb = e.bounds();
                     pp.x += eb.x+eb.w+2.0;
-                    h = h.max(eb.h);
+                    (e, at)
+                }).collect()
+            },
+            &Element::Fraction { ref numer, ref denom, script } => {
+                let nb = numer.bounds();
+                let db = denom.bounds();
+                if script {
+                    vec![(&**numer, p), (&**denom, p + Point::x(nb.w + 8.0))]
+                } else {
+                    vec![(&**numer, p - Point::xy(0.0, nb.h / 2.0 + 1.0)), (&**denom, p + Point::xy(0.0, db.h / 2.0 + 1.0))]
                 }
-                let w = pp.x-p.x;
-                draw_fence(rx, p, w, h);
+            },
+            &Element::Sqrt(ref el) => vec![(&**el, p + Point::xy(9.0, 0.0))],
+            &Element::Root { ref base, ref index } => {
+                let ib = index.bounds();
+                vec![(&**base, p + Point::xy(ib.w+7.0, 0.0)), (&**index, p - Point::xy(0.0, ib.h/2.0))]
             },
             &Element::Subscript { ref base, ref script } => {
                 let b = base.bounds();
-                base.draw(p, rx);
-                script.draw(p + Point::xy(b.w+2.0, b.h/3.0), rx);
-            }
+                vec![(&**base, p), (&**script, p + Point::xy(b.w+2.0, b.h/3.0))]
+            },
             &Element::Superscript { ref base, ref script } => {
                 let b = base.bounds();
-                base.draw(p, rx);
-                script.draw(p + Point::xy(b.w+2.0, -b.h/3.0), rx);
-            }
+                vec![(&**base, p), (&**script, p + Point::xy(b.w+2.0, -b.h/3.0))]
+            },
             &Element::Subsuperscript { ref base, ref subscript, ref superscript } => {
                 let b = base.bounds();
-                base.draw(p, rx);
-                subscript.draw(p + Point::xy(b.w+2.0, b.h/3.0), rx);
-                superscript.draw(p + Point::xy(b.w+2.0, -b.h/3.0), rx);
-            }
-            &Element::Space(size) => {}
-            _ => panic!("draw silly element")
+                vec![(&**base, p), (&**subscript, p + Point::xy(b.w+2.0, b.h/3.0)),
+                     (&**superscript, p + Point::xy(b.w+2.0, -b.h/3.0))]
+            },
+            &Element::Under { ref base, ref script } => {
+                let w = self.bounds().w;
+                let b = base.bounds();
+                let sb = script.bounds();
+                vec![(&**base, p + Point::x((w - b.w)/2.0)), (&**script, p + Point::xy((w - sb.w)/2.0, (b.h + sb.h)/2.0 + 2.0))]
+            },
+            &Element::Over { ref base, ref script } => {
+                let w = self.bounds().w;
+                let b = base.bounds();
+                let sb = script.bounds();
+                vec![(&**base, p + Point::x((w - b.w)/2.0)), (&**script, p + Point::xy((w - sb.w)/2.0, -(b.h + sb.h)/2.0 - 2.0))]
+            },
+            &Element::UnderOver { ref base, ref under, ref over } => {
+                let w = self.bounds().w;
+                let b = base.bounds();
+                let ub = under.bounds();
+                let ob = over.bounds();
+                vec![(&**base, p + Point::x((w - b.w)/2.0)),
+                     (&**under, p + Point::xy((w - ub.w)/2.0, (b.h + ub.h)/2.0 + 2.0)),
+                     (&**over, p + Point::xy((w - ob.w)/2.0, -(b.h + ob.h)/2.0 - 2.0))]
+            },
+            &Element::Table(ref rows) => {
+                let (cols, heights) = table_metrics(rows);
+                let mut placed = Vec::new();
+                let mut y = p.y - heights.iter().map(|h| h + 4.0).sum::<f32>()/2.0;
+                for (row, h) in rows.iter().zip(heights.iter()) {
+                    let mut x = p.x + 6.0;
+                    for (e, w) in row.iter().zip(cols.iter()) {
+                        let eb = e.bounds();
+                        placed.push((e, Point::xy(x + (w - eb.w)/2.0, y + 2.0 + h/2.0)));
+                        x += w + 12.0;
+                    }
+                    y += h + 4.0;
+                }
+                placed
+            },
+            _ => Vec::new()
+        }
+    }
+
+    /// the area covered by this element when drawn at `p`, which is the middle of its left edge
+    fn rect_at(&self, p: Point) -> Rect {
+        let b = self.bounds();
+        Rect::xywh(p.x, p.y - b.h/2.0, b.w, b.h)
+    }
+
+    /// add to `out` every element at `pt`, outermost first, as child index paths along with their areas
+    fn hit(&self, p: Point, pt: Point, path: &mut Vec<usize>, out: &mut Vec<(Vec<usize>, Rect)>) -> bool {
+        let r = self.rect_at(p);
+        let n = out.len();
+        out.push((path.clone(), r));
+        for (i, (c, cp)) in self.placed_children(p).into_iter().enumerate() {
+            path.push(i);
+            let found = c.hit(cp, pt, path, out);
+            path.pop();
+            if found { return true; }
+        }
+        if r.contains(pt) && !self.is_space() {
+            true
+        } else {
+            out.truncate(n);
+            false
+        }
+    }
+
+    fn is_space(&self) -> bool {
+        match self {
+            &Element::Space(_) | &Element::ParsePlaceholder => true,
+            _ => false
         }
     }
 } 
@@ -450,6 +976,9 @@ impl MathExpression {
             .add_entity("ImaginaryI", "𝑖")
             .add_entity("int", "∫")
             .add_entity("DifferentialD", "𝑑")
+            .add_entity("sum", "∑")
+            .add_entity("prod", "∏")
+            .add_entity("infin", "∞")
             .create_reader(source);
         match parser.next()? {
             XmlEvent::StartDocument { .. } => {},
@@ -458,11 +987,111 @@ impl MathExpression {
         Ok(MathExpression { root: Element::from_mathml(&mut parser, rx, font)? })
     }
 
+    pub fn root(&self) -> &Element {
+        &self.root
+    }
+
     pub fn bounds(&self) -> Rect {
         self.root.bounds()
     }
 
+    pub fn plain_text(&self) -> String {
+        let mut s = String::new();
+        // every construct has a plain text form
+        let _ = self.root.write(&PlainText, &mut s);
+        s
+    }
+
     pub fn draw(&self, p: Point, rx: &mut RenderContext) {
         self.root.draw(p, rx);
     }
+
+    /// the subexpressions under `pt`, outermost first, each as the child indices leading to it from
+    /// the root and the area it covers. Both points are relative to where the expression is drawn
+    pub fn hit(&self, pt: Point) -> Vec<(Vec<usize>, Rect)> {
+        let mut out = Vec::new();
+        self.root.hit(Point::xy(0.0, 0.0), pt, &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// the subexpression at the end of a path returned by `hit`, and the area it covers
+    pub fn element(&self, path: &[usize]) -> Option<(&Element, Rect)> {
+        let mut e = &self.root;
+        let mut p = Point::xy(0.0, 0.0);
+        for &i in path {
+            let (c, cp) = e.placed_children(p).into_iter().nth(i)?;
+            e = c;
+            p = cp;
+        }
+        Some((e, e.rect_at(p)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(s: &str) -> Element { Element::Id(String::from(s), None, 0) }
+    fn num(s: &str) -> Element { Element::Number(String::from(s), None, 0) }
+    fn op(s: &str) -> Element { Element::Operator(String::from(s), None, 0) }
+    fn row(els: Vec<Element>) -> Element { Element::Row(els) }
+    fn sup(base: Element, script: Element) -> Element { Element::Superscript { base: Box::new(base), script: Box::new(script) } }
+
+    fn text(e: &Element) -> String {
+        let mut s = String::new();
+        e.write(&PlainText, &mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn sums_and_products_read_back_as_calls() {
+        let sum = Element::UnderOver {
+            base: Box::new(op("∑")),
+            under: Box::new(row(vec![id("k"), op("="), num("1")])),
+            over: Box::new(id("n"))
+        };
+        let e = row(vec![sum, sup(id("k"), num("2"))]);
+        assert_eq!(e.input_form().unwrap(), "sum(k^2, k, 1, n)");
+        assert_eq!(text(&e), "∑_(k=1)^nk^2");
+    }
+
+    #[test]
+    fn limits_read_back_as_calls() {
+        let lim = Element::Under { base: Box::new(id("lim")), script: Box::new(row(vec![id("x"), op("→"), num("0")])) };
+        let arg = Element::Fenced { open: String::from("("), close: String::from(")"), seperator: String::from(","), children: vec![id("x")] };
+        let body = Element::Fraction { numer: Box::new(row(vec![id("sin"), op("\u{2061}"), arg])), denom: Box::new(id("x")), script: false };
+        assert_eq!(row(vec![lim, body]).input_form().unwrap(), "limit((sin(x))/x, x, 0)");
+    }
+
+    #[test]
+    fn integrals_take_their_variable_from_the_differential() {
+        let d = row(vec![op("𝑑"), id("x")]);
+        let e = row(vec![op("∫"), row(vec![sup(id("x"), num("2"))]), Element::Space(1), d.clone()]);
+        assert_eq!(e.input_form().unwrap(), "integrate(x^2, x)");
+        let definite = Element::UnderOver { base: Box::new(op("∫")), under: Box::new(num("0")), over: Box::new(num("1")) };
+        let e = row(vec![definite, row(vec![id("x")]), d]);
+        assert_eq!(e.input_form().unwrap(), "integrate(x, x, 0, 1)");
+        // without a differential there's no telling what the variable is
+        assert!(row(vec![op("∫"), id("x")]).input_form().is_none());
+    }
+
+    #[test]
+    fn stacked_parts_have_no_input_form() {
+        let sum = Element::UnderOver {
+            base: Box::new(op("∑")),
+            under: Box::new(row(vec![id("k"), op("="), num("1")])),
+            over: Box::new(id("n"))
+        };
+        // the sign on its own, without what it sums
+        assert!(sum.input_form().is_none());
+        let e = Element::Over { base: Box::new(id("v")), script: Box::new(op("→")) };
+        assert!(e.input_form().is_none());
+    }
+
+    #[test]
+    fn matrices_read_back_as_calls() {
+        let table = Element::Table(vec![vec![num("1"), num("2")], vec![num("3"), id("𝜋")]]);
+        let e = Element::Fenced { open: String::from("("), close: String::from(")"), seperator: String::from(","), children: vec![table] };
+        assert_eq!(e.input_form().unwrap(), "matrix([1, 2], [3, %pi])");
+    }
 }
@@ -0,0 +1,83 @@
+use runic::*;
+
+/// A popup list of choices drawn over the notebook, each carrying the action it stands for
+pub struct Menu<A> {
+    pub pos: Point,
+    items: Vec<(String, A)>,
+    layouts: Option<Vec<TextLayout>>,
+    /// where each item was drawn, for hit testing
+    rects: Vec<Rect>,
+    /// the item chosen with the keyboard
+    pub selected: Option<usize>
+}
+
+impl<A: Clone> Menu<A> {
+    pub fn new(pos: Point, items: Vec<(String, A)>) -> Menu<A> {
+        Menu { pos, items, layouts: None, rects: Vec::new(), selected: None }
+    }
+
+    /// move the keyboard selection up (`delta` < 0) or down, wrapping around at the ends
+    pub fn select(&mut self, delta: isize) {
+        let n = self.items.len() as isize;
+        if n == 0 { return; }
+        let i = match self.selected {
+            Some(i) => (i as isize + delta + n) % n,
+            None => if delta < 0 { n - 1 } else { 0 }
+        };
+        self.selected = Some(i as usize);
+    }
+
+    /// the action of the item chosen with the keyboard
+    pub fn chosen(&self) -> Option<A> {
+        self.selected.map(|i| self.items[i].1.clone())
+    }
+
+    /// the whole area of the menu as of the last draw
+    pub fn bounds(&self) -> Rect {
+        match (self.rects.first(), self.rects.last()) {
+            (Some(a), Some(b)) => Rect::xywh(a.x, a.y, a.w, b.y + b.h - a.y),
+            _ => Rect::xywh(self.pos.x, self.pos.y, 0.0, 0.0)
+        }
+    }
+
+    pub fn item_at(&self, p: Point) -> Option<usize> {
+        self.rects.iter().position(|r| r.contains(p))
+    }
+
+    /// the action of the item under `p`
+    pub fn pick(&self, p: Point) -> Option<A> {
+        self.item_at(p).map(|i| self.items[i].1.clone())
+    }
+
+    /// draw the menu, moving it back inside the window if it would hang off the edge, and
+    /// highlighting the selected item and the one under `mouse`
+    pub fn draw(&mut self, rx: &mut RenderContext, font: &Font, mouse: Point) {
+        if self.layouts.is_none() {
+            self.layouts = Some(self.items.iter()
+                .map(|&(ref s, _)| rx.new_text_layout(s, font, 320.0, 64.0).expect("create text layout"))
+                .collect());
+        }
+        let bnds = rx.bounds();
+        let layouts = self.layouts.as_ref().unwrap();
+        let w = layouts.iter().map(|ly| ly.bounds().w).fold(0.0, f32::max) + 16.0;
+        let h: f32 = layouts.iter().map(|ly| ly.bounds().h + 4.0).sum();
+        self.pos.x = self.pos.x.min(bnds.w - w).max(0.0);
+        self.pos.y = self.pos.y.min(bnds.h - h).max(0.0);
+
+        rx.set_color(Color::rgba(0.1, 0.1, 0.12, 0.95));
+        rx.fill_rect(Rect::xywh(self.pos.x, self.pos.y, w, h));
+        self.rects.clear();
+        let mut y = self.pos.y;
+        for (i, ly) in layouts.iter().enumerate() {
+            let r = Rect::xywh(self.pos.x, y, w, ly.bounds().h + 4.0);
+            if r.contains(mouse) || self.selected == Some(i) {
+                rx.set_color(Color::rgba(0.4, 0.4, 0.6, 0.5));
+                rx.fill_rect(r);
+            }
+            rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+            rx.draw_text_layout(Point::xy(r.x + 8.0, r.y + 2.0), ly);
+            self.rects.push(r);
+            y += r.h;
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use cell::{self, Cell, CellKind};
+
+use std::io::{self, Write, BufRead, BufReader};
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+/// A reversible change to the structure of the notebook. Applying one gives back the change that undoes it
+pub enum CellOp {
+    Insert { at: usize, cell: Cell },
+    Remove { at: usize },
+    Move { from: usize, to: usize },
+    /// split a cell's input at a byte position, moving the rest into a new cell after it
+    Split { at: usize, pos: usize },
+    /// append the next cell's input to this one, joined by `sep`, and remove the next cell
+    Join { at: usize, sep: String },
+    /// undo a `Join`, restoring the removed cell as it was
+    Unjoin { at: usize, len: usize, sep: String, cell: Cell },
+    SetKind { at: usize, kind: CellKind }
+}
+
+impl CellOp {
+    /// apply the change to `cells`, returning its inverse and the cell that should have focus afterwards
+    pub fn apply(self, cells: &mut Vec<Cell>) -> (CellOp, usize) {
+        match self {
+            CellOp::Insert { at, cell } => {
+                cells.insert(at, cell);
+                (CellOp::Remove { at }, at)
+            },
+            CellOp::Remove { at } => {
+                let cell = cells.remove(at);
+                (CellOp::Insert { at, cell }, at.min(cells.len().saturating_sub(1)))
+            },
+            CellOp::Move { from, to } => {
+                let cell = cells.remove(from);
+                cells.insert(to, cell);
+                (CellOp::Move { from: to, to: from }, to)
+            },
+            CellOp::Split { at, pos } => {
+                let rest = cells[at].input.split_off(pos);
+                cells[at].input_layout = None;
+                // both halves stay the kind the cell was
+                let mut next = Cell::with_input(rest);
+                next.set_kind(cells[at].kind);
+                cells.insert(at+1, next);
+                (CellOp::Join { at, sep: String::new() }, at+1)
+            },
+            CellOp::Join { at, sep } => {
+                let next = cells.remove(at+1);
+                let len = cells[at].input.len();
+                cells[at].input.push_str(&sep);
+                cells[at].input.push_str(&next.input);
+                cells[at].input_layout = None;
+                (CellOp::Unjoin { at, len, sep, cell: next }, at)
+            },
+            CellOp::Unjoin { at, len, sep, cell } => {
+                cells[at].input.truncate(len);
+                cells[at].input_layout = None;
+                cells.insert(at+1, cell);
+                (CellOp::Join { at, sep }, at)
+            },
+            CellOp::SetKind { at, kind } => {
+                let old = cells[at].set_kind(kind);
+                (CellOp::SetKind { at, kind: old }, at)
+            }
+        }
+    }
+}
+
+/// A reversible change to the notebook, either to its structure or to the text of one cell's input
+pub enum Command {
+    Structure(CellOp),
+    /// replace `remove`, which sits at byte `at` in the cell's input, with `insert`
+    Text { cell: usize, at: usize, remove: String, insert: String }
+}
+
+impl Command {
+    /// the text change that turns `new` back into `old`, if they differ
+    pub fn text_diff(cell: usize, old: &str, new: &str) -> Option<Command> {
+        if old == new { return None; }
+        let prefix = old.char_indices().zip(new.chars())
+            .find(|&((_, a), b)| a != b).map(|((i, _), _)| i)
+            .unwrap_or(old.len().min(new.len()));
+        let max_suffix = old.len().min(new.len()) - prefix;
+        let suffix = old[prefix..].chars().rev().zip(new[prefix..].chars().rev())
+            .take_while(|&(a, b)| a == b).map(|(a, _)| a.len_utf8())
+            .scan(0, |n, l| { *n += l; Some(*n) }).take_while(|&n| n <= max_suffix).last().unwrap_or(0);
+        Some(Command::Text {
+            cell, at: prefix,
+            remove: String::from(&new[prefix..new.len()-suffix]),
+            insert: String::from(&old[prefix..old.len()-suffix])
+        })
+    }
+
+    /// apply the change to `cells`, returning its inverse along with the cell and caret position that should have focus afterwards
+    pub fn apply(self, cells: &mut Vec<Cell>) -> (Command, usize, usize) {
+        match self {
+            Command::Structure(op) => {
+                let (inv, focus) = op.apply(cells);
+                (Command::Structure(inv), focus, 0)
+            },
+            Command::Text { cell, at, remove, insert } => {
+                {
+                    let c = &mut cells[cell];
+                    c.input = format!("{}{}{}", &c.input[..at], insert, &c.input[at+remove.len()..]);
+                    c.input_layout = None;
+                }
+                let caret = at + insert.len();
+                (Command::Text { cell, at, remove: insert, insert: remove }, cell, caret)
+            }
+        }
+    }
+
+    /// fold a later single-character edit into this one, so that typing or deleting a word undoes in one step
+    fn coalesce(&mut self, next: &Command) -> bool {
+        fn small(s: &str) -> bool {
+            s.chars().count() == 1 && !s.chars().any(|c| c.is_whitespace())
+        }
+        match (self, next) {
+            (&mut Command::Text { cell, at, ref mut remove, ref insert },
+             &Command::Text { cell: ncell, at: nat, remove: ref nremove, insert: ref ninsert })
+                if cell == ncell && insert.is_empty() && ninsert.is_empty() && small(nremove) && nat == at + remove.len() => {
+                    // typing: the undo removes an ever longer run of text
+                    remove.push_str(nremove);
+                    true
+                },
+            (&mut Command::Text { cell, ref mut at, ref remove, ref mut insert },
+             &Command::Text { cell: ncell, at: nat, remove: ref nremove, insert: ref ninsert })
+                if cell == ncell && remove.is_empty() && nremove.is_empty() && small(ninsert) && nat + ninsert.len() == *at => {
+                    // backspacing: the undo puts back an ever longer run of text
+                    *at = nat;
+                    insert.insert_str(0, ninsert);
+                    true
+                },
+            _ => false
+        }
+    }
+}
+
+/// Undo and redo stacks of commands
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>
+}
+
+impl History {
+    pub fn new() -> History {
+        History { undo: Vec::new(), redo: Vec::new() }
+    }
+
+    /// remember how to undo a change that was just made
+    pub fn record(&mut self, inverse: Command) {
+        self.redo.clear();
+        if let Some(top) = self.undo.last_mut() {
+            if top.coalesce(&inverse) { return; }
+        }
+        self.undo.push(inverse);
+    }
+
+    /// undo the last change, returning the cell and caret position to focus
+    pub fn undo(&mut self, cells: &mut Vec<Cell>) -> Option<(usize, usize)> {
+        self.undo.pop().map(|cmd| {
+            let (inv, cell, caret) = cmd.apply(cells);
+            self.redo.push(inv);
+            (cell, caret)
+        })
+    }
+
+    /// redo the last undone change, returning the cell and caret position to focus
+    pub fn redo(&mut self, cells: &mut Vec<Cell>) -> Option<(usize, usize)> {
+        self.redo.pop().map(|cmd| {
+            let (inv, cell, caret) = cmd.apply(cells);
+            self.undo.push(inv);
+            (cell, caret)
+        })
+    }
+}
+
+/// which cells are shown, given that a collapsed heading hides everything up to the next heading at its level or above
+pub fn visibility(cells: &[Cell]) -> Vec<bool> {
+    let mut hiding: Option<usize> = None;
+    cells.iter().map(|c| {
+        match c.kind.heading_level() {
+            Some(level) => {
+                if hiding.map_or(false, |h| level <= h) { hiding = None; }
+                let visible = hiding.is_none();
+                if visible && c.collapsed { hiding = Some(level); }
+                visible
+            },
+            None => hiding.is_none()
+        }
+    }).collect()
+}
+
+const HEADER: &'static str = "rmaxima notebook 2";
+
+/// Write the cells' inputs out as a notebook file. Each cell is a `%% kind` line, with any flags,
+/// the last evaluation's time in seconds as `time=` and the number of lines its text takes as
+/// `lines=`, followed by that many lines of text. The text is only split at line feeds, so that it
+/// comes back exactly as it was, carriage returns and final line break included. Text cells keep
+/// their formatting in their text, as the markers `markup::parse` reads
+pub fn save<P: AsRef<Path>>(path: P, cells: &[Cell]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    writeln!(f, "{}", HEADER)?;
+    for c in cells {
+        write!(f, "%% {}", c.kind.name())?;
+        if c.collapsed { write!(f, " collapsed")?; }
+        if let Some(d) = c.elapsed { write!(f, " time={:.3}", cell::duration_secs(d))?; }
+        writeln!(f, " lines={}", c.input.split('\n').count())?;
+        for line in c.input.split('\n') {
+            writeln!(f, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a notebook file written by `save`. The cells come back unevaluated
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Cell>> {
+    let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+    let mut lines = BufReader::new(File::open(path)?).split(b'\n')
+        .map(|l| l.and_then(|l| String::from_utf8(l).map_err(|_| bad("notebook isn't UTF-8"))));
+    match lines.next() {
+        Some(Ok(ref h)) if h.trim_right() == HEADER => {},
+        _ => return Err(bad("not an rmaxima notebook"))
+    }
+    let mut cells: Vec<Cell> = Vec::new();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if !line.starts_with("%%") {
+            return Err(bad("text outside of a cell"));
+        }
+        let mut words = line[2..].split_whitespace();
+        let kind = words.next().and_then(CellKind::from_name).ok_or_else(|| bad("unknown cell kind"))?;
+        let mut c = Cell::empty(None);
+        c.set_kind(kind);
+        let mut count = 0;
+        for w in words {
+            if w == "collapsed" {
+                c.collapsed = true;
+            } else if w.starts_with("time=") {
+                let t: f64 = w[5..].parse().map_err(|_| bad("bad evaluation time"))?;
+                c.set_elapsed(Duration::from_millis((t * 1000.0) as u64));
+            } else if w.starts_with("lines=") {
+                count = w[6..].parse().map_err(|_| bad("bad line count"))?;
+            }
+        }
+        let mut text = Vec::with_capacity(count);
+        for _ in 0..count {
+            text.push(lines.next().unwrap_or_else(|| Err(bad("notebook ends inside a cell")))?);
+        }
+        c.input = text.join("\n");
+        cells.push(c);
+    }
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use markup::{self, Style};
+
+    fn cells(inputs: &[&str]) -> Vec<Cell> {
+        inputs.iter().map(|s| Cell::with_input(String::from(*s))).collect()
+    }
+
+    fn inputs(cells: &[Cell]) -> Vec<&str> {
+        cells.iter().map(|c| c.input.as_str()).collect()
+    }
+
+    /// the text change as (at, remove, insert)
+    fn diff(old: &str, new: &str) -> Option<(usize, String, String)> {
+        match Command::text_diff(0, old, new) {
+            Some(Command::Text { cell: 0, at, remove, insert }) => Some((at, remove, insert)),
+            Some(_) => panic!("text diff gave another kind of command"),
+            None => None
+        }
+    }
+
+    /// make an edit to the first cell, recording it the way `MaximaApp::text_edit` does
+    fn type_to(h: &mut History, cells: &mut Vec<Cell>, text: &str) {
+        let old = ::std::mem::replace(&mut cells[0].input, String::from(text));
+        if let Some(inv) = Command::text_diff(0, &old, text) {
+            h.record(inv);
+        }
+    }
+
+    #[test]
+    fn text_diff_finds_the_changed_middle() {
+        assert!(diff("abc", "abc").is_none());
+        assert_eq!(diff("hello world", "hello there world"), Some((6, String::from("there "), String::new())));
+        assert_eq!(diff("hello there world", "hello world"), Some((6, String::new(), String::from("there "))));
+        assert_eq!(diff("x+1", "x-1"), Some((1, String::from("-"), String::from("+"))));
+        // the common start and end may not overlap when a repeated character is added
+        assert_eq!(diff("aa", "aaa"), Some((2, String::from("a"), String::new())));
+        // and are measured in whole characters
+        assert_eq!(diff("αβ", "αγβ"), Some((2, String::from("γ"), String::new())));
+    }
+
+    #[test]
+    fn text_edits_undo_and_redo() {
+        let mut c = cells(&["f(x) := x^2;"]);
+        let mut h = History::new();
+        type_to(&mut h, &mut c, "f(x) := x^3;");
+        type_to(&mut h, &mut c, "g(x) := x^3;");
+        assert_eq!(h.undo(&mut c), Some((0, 1)));
+        assert_eq!(inputs(&c), ["f(x) := x^3;"]);
+        assert_eq!(h.undo(&mut c), Some((0, 11)));
+        assert_eq!(inputs(&c), ["f(x) := x^2;"]);
+        assert_eq!(h.undo(&mut c), None);
+        assert_eq!(h.redo(&mut c), Some((0, 11)));
+        assert_eq!(h.redo(&mut c), Some((0, 1)));
+        assert_eq!(inputs(&c), ["g(x) := x^3;"]);
+        assert_eq!(h.redo(&mut c), None);
+    }
+
+    #[test]
+    fn typing_a_word_undoes_in_one_step() {
+        let mut c = cells(&[""]);
+        let mut h = History::new();
+        for s in &["a", "ab", "abc", "abc ", "abc d"] {
+            type_to(&mut h, &mut c, s);
+        }
+        // a space isn't folded into the word before it, so it starts the next step
+        h.undo(&mut c);
+        assert_eq!(inputs(&c), ["abc"]);
+        assert_eq!(h.undo(&mut c), Some((0, 0)));
+        assert_eq!(inputs(&c), [""]);
+        assert_eq!(h.redo(&mut c), Some((0, 3)));
+        assert_eq!(inputs(&c), ["abc"]);
+    }
+
+    #[test]
+    fn backspacing_undoes_in_one_step() {
+        let mut c = cells(&["sin(x)"]);
+        let mut h = History::new();
+        for s in &["sin(x", "sin(", "sin"] {
+            type_to(&mut h, &mut c, s);
+        }
+        assert_eq!(h.undo(&mut c), Some((0, 6)));
+        assert_eq!(inputs(&c), ["sin(x)"]);
+        assert_eq!(h.undo(&mut c), None);
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_stack() {
+        let mut c = cells(&[""]);
+        let mut h = History::new();
+        type_to(&mut h, &mut c, "a");
+        h.undo(&mut c);
+        type_to(&mut h, &mut c, "b");
+        assert_eq!(h.redo(&mut c), None);
+        assert_eq!(inputs(&c), ["b"]);
+    }
+
+    /// apply `op`, check the result, then check that its inverse puts everything back and that
+    /// the inverse of that does the change again
+    fn round_trip(start: &[&str], op: CellOp, after: &[&str], focus: usize) {
+        let mut c = cells(start);
+        let (inv, f) = op.apply(&mut c);
+        assert_eq!(inputs(&c), after);
+        assert_eq!(f, focus);
+        let (redo, _) = inv.apply(&mut c);
+        assert_eq!(inputs(&c), start);
+        redo.apply(&mut c);
+        assert_eq!(inputs(&c), after);
+    }
+
+    #[test]
+    fn insert_and_remove_invert() {
+        round_trip(&["a", "b"], CellOp::Insert { at: 1, cell: Cell::with_input(String::from("new")) }, &["a", "new", "b"], 1);
+        round_trip(&["a", "b", "c"], CellOp::Remove { at: 1 }, &["a", "c"], 1);
+        round_trip(&["a", "b"], CellOp::Remove { at: 1 }, &["a"], 0);
+    }
+
+    #[test]
+    fn move_inverts() {
+        round_trip(&["a", "b", "c"], CellOp::Move { from: 0, to: 2 }, &["b", "c", "a"], 2);
+        round_trip(&["a", "b", "c"], CellOp::Move { from: 2, to: 1 }, &["a", "c", "b"], 1);
+    }
+
+    #[test]
+    fn split_and_join_invert() {
+        round_trip(&["x: 1;\ny: 2;", "z"], CellOp::Split { at: 0, pos: 6 }, &["x: 1;\n", "y: 2;", "z"], 1);
+        round_trip(&["x: 1;", "y: 2;", "z"], CellOp::Join { at: 0, sep: String::from("\n") }, &["x: 1;\ny: 2;", "z"], 0);
+    }
+
+    #[test]
+    fn split_and_join_keep_kinds() {
+        let kinds = |c: &[Cell]| c.iter().map(|c| c.kind).collect::<Vec<_>>();
+        let mut c = cells(&["First part.\nSecond part.", "Results"]);
+        c[0].set_kind(CellKind::Text);
+        c[1].set_kind(CellKind::Section);
+        let (join, _) = CellOp::Split { at: 0, pos: 12 }.apply(&mut c);
+        assert_eq!(kinds(&c), [CellKind::Text, CellKind::Text, CellKind::Section]);
+        let (split, _) = join.apply(&mut c);
+        assert_eq!(kinds(&c), [CellKind::Text, CellKind::Section]);
+        split.apply(&mut c);
+        assert_eq!(inputs(&c), ["First part.\n", "Second part.", "Results"]);
+        assert_eq!(kinds(&c), [CellKind::Text, CellKind::Text, CellKind::Section]);
+        // joining a heading onto text and undoing it brings the heading back
+        let (unjoin, _) = CellOp::Join { at: 1, sep: String::from("\n") }.apply(&mut c);
+        assert_eq!(kinds(&c), [CellKind::Text, CellKind::Text]);
+        unjoin.apply(&mut c);
+        assert_eq!(kinds(&c), [CellKind::Text, CellKind::Text, CellKind::Section]);
+    }
+
+    #[test]
+    fn set_kind_inverts() {
+        let mut c = cells(&["Results"]);
+        let (inv, _) = CellOp::SetKind { at: 0, kind: CellKind::Section }.apply(&mut c);
+        assert!(c[0].kind == CellKind::Section);
+        inv.apply(&mut c);
+        assert!(c[0].kind == CellKind::Code);
+    }
+
+    #[test]
+    fn notebooks_save_and_load_unchanged() {
+        let mut c = cells(&["f(x) := x^2;\n", "block(\r\n  a: 1,\r\n  b)", "", "%% code lines=3", "x;\n\n", "Results"]);
+        c[1].set_elapsed(Duration::from_millis(1500));
+        c[5].set_kind(CellKind::Section);
+        c[5].collapsed = true;
+        let path = ::std::env::temp_dir().join(format!("rmaxima-test-{}.mxnb", ::std::process::id()));
+        save(&path, &c).unwrap();
+        let loaded = load(&path);
+        ::std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(inputs(&loaded), inputs(&c));
+        assert!(loaded.iter().zip(c.iter()).all(|(a, b)| a.kind == b.kind && a.collapsed == b.collapsed));
+        assert_eq!(loaded[1].elapsed, Some(Duration::from_millis(1500)));
+        assert_eq!(loaded[0].elapsed, None);
+    }
+
+    #[test]
+    fn formatting_saves_and_loads_unchanged() {
+        let mut c = cells(&["Here **f** is *even*,\nso `f(-x) = f(x)`.", "f(x) := x^2;"]);
+        c[0].set_kind(CellKind::Text);
+        let path = ::std::env::temp_dir().join(format!("rmaxima-test-{}-formatted.mxnb", ::std::process::id()));
+        save(&path, &c).unwrap();
+        let loaded = load(&path);
+        ::std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert!(loaded[0].kind == CellKind::Text);
+        assert_eq!(inputs(&loaded), inputs(&c));
+        let styles: Vec<(Style, &str)> = markup::parse(&loaded[0].input).into_iter()
+            .map(|s| (s.style, &loaded[0].input[s.inner()])).collect();
+        assert_eq!(styles, vec![(Style::Bold, "f"), (Style::Italic, "even"), (Style::Code, "f(-x) = f(x)")]);
+    }
+
+    #[test]
+    fn structure_changes_undo_through_the_history() {
+        let mut c = cells(&["a", "b"]);
+        let mut h = History::new();
+        let (inv, _) = CellOp::Join { at: 0, sep: String::from("\n") }.apply(&mut c);
+        h.record(Command::Structure(inv));
+        assert_eq!(inputs(&c), ["a\nb"]);
+        h.undo(&mut c);
+        assert_eq!(inputs(&c), ["a", "b"]);
+        h.redo(&mut c);
+        assert_eq!(inputs(&c), ["a\nb"]);
+    }
+}
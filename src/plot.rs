@@ -0,0 +1,912 @@
+use runic::*;
+
+use xml::reader::{ParserConfig, XmlEvent};
+use xml::attribute::OwnedAttribute;
+
+use cell::Fonts;
+
+use std::io::Read;
+use std::error::Error;
+use std::fmt::Write;
+
+/// Lisp run at startup, after the MathML printer is loaded, which defines a `plot2d` that samples
+/// each function and prints the points between <plot></plot> instead of running gnuplot.
+/// Parametric curves are sampled over their parameter, and discrete ones are given either as a list
+/// of xs and a list of ys or as a list of `[x, y]` pairs. Each line inside is an option as
+/// `name|value|...`, with the horizontal range as `x|lo|hi` whatever its variable is called,
+/// `curve|label` to start a curve, `p x y` for a point, or `gap` where the function has no real value
+pub const PLOT_LISP: &'static str = "\
+(defun rm-plot-option (o) \
+  (when (symbolp (cadr o)) \
+    (format t \"~a~{|~a~}~%\" (string-downcase (subseq (symbol-name (cadr o)) 1)) (mapcar #'$sconcat (cddr o))))) \
+(defun rm-plot-points (xs ys) \
+  (mapc #'(lambda (x y) (if (and (realp x) (realp y)) (format t \"p ~f ~f~%\" x y) (format t \"gap~%\"))) xs ys)) \
+(defun rm-plot-samples (f v lo hi) \
+  (let ((ff (coerce-float-fun f (list '(mlist) v))) (lo ($float lo)) (hi ($float hi))) \
+    (loop for i from 0 to 400 collect (ignore-errors (funcall ff (+ lo (* i (/ (- hi lo) 400)))))))) \
+(defun rm-plot2d (fun &rest options) \
+  (let* ((range (find-if #'(lambda (o) (and ($listp o) (= (length o) 4) (not (member (cadr o) '($y $legend $style $color))))) options)) \
+         (funs (if (and ($listp fun) (not (member (cadr fun) '($discrete $parametric)))) (cdr fun) (list fun)))) \
+    (princ \"<plot>\") (terpri) \
+    (unwind-protect \
+      (progn \
+        (mapc #'rm-plot-option (remove range (remove-if-not #'$listp options))) \
+        (when range (format t \"x|~f|~f~%\" ($float (caddr range)) ($float (cadddr range)))) \
+        (dolist (f funs) \
+          (format t \"curve|~a~%\" ($string f)) \
+          (cond \
+            ((and ($listp f) (eq (cadr f) '$discrete) (cdddr f)) \
+             (rm-plot-points (mapcar #'$float (cdr (caddr f))) (mapcar #'$float (cdr (cadddr f))))) \
+            ((and ($listp f) (eq (cadr f) '$discrete)) \
+             (flet ((part (n) (mapcar #'(lambda (p) (and ($listp p) (= (length p) 3) ($float (nth n p)))) (cdr (caddr f))))) \
+               (rm-plot-points (part 1) (part 2)))) \
+            ((and ($listp f) (eq (cadr f) '$parametric)) \
+             (let ((tr (fifth f))) \
+               (rm-plot-points (rm-plot-samples (caddr f) (cadr tr) (caddr tr) (cadddr tr)) \
+                               (rm-plot-samples (cadddr f) (cadr tr) (caddr tr) (cadddr tr))))) \
+            (t \
+             (rm-plot-points (loop for i from 0 to 400 collect (+ ($float (caddr range)) (* i (/ (- ($float (cadddr range)) ($float (caddr range))) 400)))) \
+                             (rm-plot-samples f (cadr range) (caddr range) (cadddr range))))))) \
+      (princ \"</plot>\") (terpri)) \
+    '$done))";
+
+/// Lisp run after the rest of the plotting Lisp, which puts the native plotting functions in place
+/// of Maxima's. Loading a package like `implicit_plot` would bring back the gnuplot version, so
+/// `load` is wrapped to put them back each time
+pub const INSTALL_LISP: &'static str = "\
+(defun rm-install-plots () \
+  (setf (symbol-function '$plot2d) #'rm-plot2d) \
+  (setf (symbol-function '$plot3d) #'rm-plot3d) \
+  (setf (symbol-function '$implicit_plot) #'rm-implicit-plot) \
+  (setf (symbol-function '$contour_plot) #'rm-contour-plot)) \
+(let ((old-load (symbol-function '$load))) \
+  (setf (symbol-function '$load) #'(lambda (&rest args) (prog1 (apply old-load args) (rm-install-plots))))) \
+(rm-install-plots)";
+
+/// A color in a figure. Unlike a `Color` its parts can be read back, for writing SVG
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rgb(pub f32, pub f32, pub f32);
+
+impl Rgb {
+    pub fn color(&self) -> Color {
+        Color::rgb(self.0, self.1, self.2)
+    }
+
+    pub fn svg(&self) -> String {
+        let c = |v: f32| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+        format!("#{:02x}{:02x}{:02x}", c(self.0), c(self.1), c(self.2))
+    }
+}
+
+/// Which end of a piece of text its position marks
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Anchor {
+    Start, Middle, End
+}
+
+/// Something drawn in a figure, in the figure's own coordinates
+pub enum Shape {
+    /// connected line segments, closed if the last point repeats the first
+    Line { points: Vec<Point>, color: Rgb, width: f32 },
+    /// a filled polygon
+    Fill { points: Vec<Point>, color: Rgb },
+    Text { at: Point, text: String, anchor: Anchor, size: f32, color: Rgb }
+}
+
+/// A plot, as shapes in a `width` by `height` area with the origin at the top left and y going down.
+/// It is scaled to fit whatever width it is drawn in
+pub struct Figure {
+    pub width: f32,
+    pub height: f32,
+    pub shapes: Vec<Shape>,
+    /// the scale the figure was last drawn at, and its text laid out at that scale
+    scale: f32,
+    layouts: Option<Vec<Option<TextLayout>>>,
+    /// each fill broken into rows of pixels at that scale, measured from the figure's corner
+    spans: Vec<Vec<Rect>>
+}
+
+/// An affine transform, as the `a b c d e f` of SVG's `matrix()`
+#[derive(Copy, Clone, Debug)]
+struct Transform([f32; 6]);
+
+impl Transform {
+    fn identity() -> Transform {
+        Transform([1.0, 0.0, 0.0, 1.0, 0.0, 0.0])
+    }
+
+    /// the transform that applies `t` first and then `self`
+    fn then(&self, t: &Transform) -> Transform {
+        let (a, b) = (&self.0, &t.0);
+        Transform([
+            a[0]*b[0] + a[2]*b[1], a[1]*b[0] + a[3]*b[1],
+            a[0]*b[2] + a[2]*b[3], a[1]*b[2] + a[3]*b[3],
+            a[0]*b[4] + a[2]*b[5] + a[4], a[1]*b[4] + a[3]*b[5] + a[5]
+        ])
+    }
+
+    fn apply(&self, x: f32, y: f32) -> Point {
+        let m = &self.0;
+        Point::xy(m[0]*x + m[2]*y + m[4], m[1]*x + m[3]*y + m[5])
+    }
+
+    /// how much the transform stretches lengths, on average
+    fn scale(&self) -> f32 {
+        let m = &self.0;
+        (m[0]*m[3] - m[1]*m[2]).abs().sqrt()
+    }
+
+    /// read a `transform` attribute, like `translate(10,20) rotate(270)`
+    fn parse(s: &str) -> Transform {
+        let mut t = Transform::identity();
+        for part in s.split(')') {
+            let mut kv = part.splitn(2, '(');
+            let name = kv.next().unwrap_or("").trim().trim_left_matches(',').trim();
+            let args = numbers(kv.next().unwrap_or(""));
+            let arg = |i: usize, default: f32| args.get(i).cloned().unwrap_or(default);
+            let m = match name {
+                "translate" => [1.0, 0.0, 0.0, 1.0, arg(0, 0.0), arg(1, 0.0)],
+                "scale" => [arg(0, 1.0), 0.0, 0.0, arg(1, arg(0, 1.0)), 0.0, 0.0],
+                "rotate" => {
+                    let (s, c) = arg(0, 0.0).to_radians().sin_cos();
+                    [c, s, -s, c, 0.0, 0.0]
+                },
+                "matrix" if args.len() == 6 => [args[0], args[1], args[2], args[3], args[4], args[5]],
+                _ => continue
+            };
+            t = t.then(&Transform(m));
+        }
+        t
+    }
+}
+
+/// A piece of SVG path data or of any other list of numbers
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Token {
+    Command(char),
+    Number(f32)
+}
+
+/// split `s` into numbers and the letters between them, skipping separators
+fn tokens(s: &str) -> Vec<Token> {
+    let b = s.as_bytes();
+    let digits = |mut i: usize| { while i < b.len() && b[i].is_ascii_digit() { i += 1; } i };
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        let c = b[i];
+        if c.is_ascii_digit() || c == b'.' || c == b'-' || c == b'+' {
+            let start = i;
+            if c == b'-' || c == b'+' { i += 1; }
+            i = digits(i);
+            // a second decimal point starts the next number, as in `1.5.5`
+            if i < b.len() && b[i] == b'.' { i = digits(i + 1); }
+            if i < b.len() && (b[i] == b'e' || b[i] == b'E') {
+                let mut j = i + 1;
+                if j < b.len() && (b[j] == b'-' || b[j] == b'+') { j += 1; }
+                if j < b.len() && b[j].is_ascii_digit() { i = digits(j); }
+            }
+            match s[start..i].parse() {
+                Ok(x) => out.push(Token::Number(x)),
+                Err(_) => i = start + 1
+            }
+        } else {
+            if c.is_ascii_alphabetic() { out.push(Token::Command(c as char)); }
+            i += 1;
+        }
+    }
+    out
+}
+
+/// every number in `s`, however they are separated
+fn numbers(s: &str) -> Vec<f32> {
+    tokens(s).into_iter().filter_map(|t| match t { Token::Number(x) => Some(x), _ => None }).collect()
+}
+
+/// read an SVG paint: `none`, `rgb(r, g, b)`, `#rrggbb`, `#rgb` or a few common names.
+/// `currentColor` is whatever `color` was inherited
+fn parse_paint(s: &str, current: Rgb) -> Option<Option<Rgb>> {
+    let s = s.trim();
+    let hex = |s: &str| u32::from_str_radix(s, 16).ok().map(|v| v as f32 / 255.0);
+    Some(Some(match s {
+        "none" | "transparent" => return Some(None),
+        "currentColor" => current,
+        "black" => Rgb(0.0, 0.0, 0.0),
+        "white" => Rgb(1.0, 1.0, 1.0),
+        "gray" | "grey" => Rgb(0.5, 0.5, 0.5),
+        "red" => Rgb(1.0, 0.0, 0.0),
+        "green" => Rgb(0.0, 0.5, 0.0),
+        "blue" => Rgb(0.0, 0.0, 1.0),
+        _ if s.starts_with("rgb(") => {
+            let v = numbers(&s[4..]);
+            if v.len() < 3 { return None; }
+            Rgb(v[0] / 255.0, v[1] / 255.0, v[2] / 255.0)
+        },
+        _ if s.starts_with('#') && s.len() == 7 => Rgb(hex(&s[1..3])?, hex(&s[3..5])?, hex(&s[5..7])?),
+        _ if s.starts_with('#') && s.len() == 4 => {
+            let d = |i: usize| hex(&s[i..i+1]).map(|v| v * 17.0);
+            Rgb(d(1)?, d(2)?, d(3)?)
+        },
+        _ => return None
+    }))
+}
+
+/// What an SVG element inherits from the ones around it
+#[derive(Clone)]
+struct Style {
+    transform: Transform,
+    stroke: Option<Rgb>,
+    fill: Option<Rgb>,
+    color: Rgb,
+    stroke_width: f32,
+    font_size: f32,
+    anchor: Anchor
+}
+
+impl Style {
+    fn apply(&mut self, key: &str, value: &str) {
+        match key {
+            "transform" => self.transform = self.transform.then(&Transform::parse(value)),
+            "color" => if let Some(Some(c)) = parse_paint(value, self.color) { self.color = c },
+            "stroke" => if let Some(c) = parse_paint(value, self.color) { self.stroke = c },
+            "fill" => if let Some(c) = parse_paint(value, self.color) { self.fill = c },
+            "stroke-width" => if let Some(&w) = numbers(value).first() { self.stroke_width = w },
+            "font-size" => if let Some(&s) = numbers(value).first() { self.font_size = s },
+            "text-anchor" => self.anchor = match value.trim() {
+                "middle" => Anchor::Middle,
+                "end" => Anchor::End,
+                _ => Anchor::Start
+            },
+            _ => {}
+        }
+    }
+
+    /// the style of an element with these attributes inside one with this style. `color` goes first
+    /// since `currentColor` in the other attributes refers to it
+    fn child(&self, attributes: &[OwnedAttribute]) -> Style {
+        let mut s = self.clone();
+        let mut props: Vec<(&str, &str)> = attributes.iter().map(|a| (a.name.local_name.as_str(), a.value.as_str())).collect();
+        if let Some(style) = attributes.iter().find(|a| a.name.local_name == "style") {
+            props.extend(style.value.split(';').filter_map(|p| {
+                let mut kv = p.splitn(2, ':');
+                Some((kv.next()?.trim(), kv.next()?.trim()))
+            }));
+        }
+        props.sort_by_key(|&(k, _)| k != "color");
+        for (k, v) in props {
+            s.apply(k, v);
+        }
+        s
+    }
+}
+
+fn attr<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|a| a.name.local_name == name).map(|a| a.value.as_str())
+}
+
+fn attr_num(attributes: &[OwnedAttribute], name: &str) -> f32 {
+    attr(attributes, name).and_then(|v| numbers(v).first().cloned()).unwrap_or(0.0)
+}
+
+/// Split path data into its subpaths, each with whether it was closed. Curves are replaced by
+/// straight lines to their end points, which is close enough for plots, whose curves are already
+/// made of short segments
+fn parse_path(d: &str) -> Vec<(Vec<(f32, f32)>, bool)> {
+    let mut paths: Vec<(Vec<(f32, f32)>, bool)> = Vec::new();
+    let (mut x, mut y) = (0.0, 0.0);
+    let mut cmd = 'M';
+    let mut args = Vec::new();
+    for tok in tokens(d) {
+        match tok {
+            Token::Command(c) => {
+                cmd = c;
+                args.clear();
+                if c == 'Z' || c == 'z' {
+                    if let Some(&mut (ref mut pts, ref mut closed)) = paths.last_mut() {
+                        if let Some(&p) = pts.first() {
+                            pts.push(p);
+                            x = p.0; y = p.1;
+                        }
+                        *closed = true;
+                    }
+                }
+            },
+            Token::Number(v) => {
+                args.push(v);
+                // how many arguments one use of the command takes; it can repeat without being named again
+                let argc = match cmd.to_ascii_uppercase() {
+                    'M' | 'L' | 'T' => 2, 'H' | 'V' => 1, 'S' | 'Q' => 4, 'C' => 6, 'A' => 7, _ => 0
+                };
+                if args.len() < argc || argc == 0 { continue; }
+                let (ox, oy) = if cmd.is_ascii_lowercase() { (x, y) } else { (0.0, 0.0) };
+                match cmd.to_ascii_uppercase() {
+                    'H' => x = ox + args[0],
+                    'V' => y = oy + args[0],
+                    _ => { x = ox + args[argc-2]; y = oy + args[argc-1]; }
+                }
+                args.clear();
+                if cmd == 'M' || cmd == 'm' {
+                    paths.push((vec![(x, y)], false));
+                    // further pairs after a move are lines
+                    cmd = if cmd == 'm' { 'l' } else { 'L' };
+                } else {
+                    if paths.is_empty() { paths.push((vec![(0.0, 0.0)], false)); }
+                    paths.last_mut().unwrap().0.push((x, y));
+                }
+            }
+        }
+    }
+    paths
+}
+
+impl Figure {
+    pub fn new(width: f32, height: f32, shapes: Vec<Shape>) -> Figure {
+        Figure { width, height, shapes, scale: 1.0, layouts: None, spans: Vec::new() }
+    }
+
+    /// Read the SVG that gnuplot writes for its `svg` terminal. Only what gnuplot uses is
+    /// understood: groups, paths, lines, polylines, polygons, rectangles, circles and text
+    pub fn from_svg<R: Read>(source: R) -> Result<Figure, Box<Error>> {
+        let parser = ParserConfig::new().trim_whitespace(false).create_reader(source);
+        let mut styles = vec![Style {
+            transform: Transform::identity(), stroke: None, fill: Some(Rgb(0.0, 0.0, 0.0)),
+            color: Rgb(0.0, 0.0, 0.0), stroke_width: 1.0, font_size: 12.0, anchor: Anchor::Start
+        }];
+        let mut size = None;
+        let mut shapes = Vec::new();
+        // text being read, with where it goes and the style it started with
+        let mut text: Option<(String, Point, Style)> = None;
+        // inside <defs>, <clipPath> and the like nothing is drawn directly
+        let mut hidden = 0;
+        for e in parser {
+            match e? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let st = styles.last().unwrap().child(&attributes);
+                    let tag = name.local_name.as_str();
+                    if hidden > 0 || tag == "defs" || tag == "clipPath" || tag == "symbol" || tag == "title" || tag == "desc" || tag == "script" {
+                        hidden += 1;
+                        styles.push(st);
+                        continue;
+                    }
+                    let t = st.transform;
+                    let outline = |points: Vec<Point>, shapes: &mut Vec<Shape>, closed: bool| {
+                        if points.len() < 2 { return; }
+                        if let (true, Some(c)) = (closed, st.fill) {
+                            shapes.push(Shape::Fill { points: points.clone(), color: c });
+                        }
+                        if let Some(c) = st.stroke {
+                            shapes.push(Shape::Line { points, color: c, width: st.stroke_width * t.scale() });
+                        }
+                    };
+                    match tag {
+                        "svg" if size.is_none() => {
+                            let vb = attr(&attributes, "viewBox").map(numbers).unwrap_or(Vec::new());
+                            size = Some(if vb.len() == 4 {
+                                (vb[2], vb[3])
+                            } else {
+                                (attr_num(&attributes, "width"), attr_num(&attributes, "height"))
+                            });
+                        },
+                        "path" => {
+                            for (pts, closed) in parse_path(attr(&attributes, "d").unwrap_or("")) {
+                                outline(pts.into_iter().map(|(x, y)| t.apply(x, y)).collect(), &mut shapes, closed);
+                            }
+                        },
+                        "polyline" | "polygon" => {
+                            let v = numbers(attr(&attributes, "points").unwrap_or(""));
+                            let mut pts: Vec<Point> = v.chunks(2).filter(|c| c.len() == 2).map(|c| t.apply(c[0], c[1])).collect();
+                            let closed = tag == "polygon";
+                            if closed && !pts.is_empty() { let p = pts[0]; pts.push(p); }
+                            outline(pts, &mut shapes, closed);
+                        },
+                        "line" => {
+                            let a = t.apply(attr_num(&attributes, "x1"), attr_num(&attributes, "y1"));
+                            let b = t.apply(attr_num(&attributes, "x2"), attr_num(&attributes, "y2"));
+                            outline(vec![a, b], &mut shapes, false);
+                        },
+                        "rect" => {
+                            let (x, y) = (attr_num(&attributes, "x"), attr_num(&attributes, "y"));
+                            let (w, h) = (attr_num(&attributes, "width"), attr_num(&attributes, "height"));
+                            let pts = vec![t.apply(x, y), t.apply(x+w, y), t.apply(x+w, y+h), t.apply(x, y+h), t.apply(x, y)];
+                            outline(pts, &mut shapes, true);
+                        },
+                        "circle" => {
+                            let (cx, cy, r) = (attr_num(&attributes, "cx"), attr_num(&attributes, "cy"), attr_num(&attributes, "r"));
+                            let pts = (0..17).map(|i| {
+                                let (s, c) = (i as f32 * ::std::f32::consts::PI / 8.0).sin_cos();
+                                t.apply(cx + r*c, cy + r*s)
+                            }).collect();
+                            outline(pts, &mut shapes, true);
+                        },
+                        "use" => {
+                            // gnuplot draws point symbols by reference to shapes in <defs>; a small
+                            // cross in the symbol's color stands in for all of them
+                            let at = t.apply(attr_num(&attributes, "x"), attr_num(&attributes, "y"));
+                            let c = st.stroke.unwrap_or(st.color);
+                            shapes.push(Shape::Line { points: vec![at - Point::xy(3.0, 3.0), at + Point::xy(3.0, 3.0)], color: c, width: 1.0 });
+                            shapes.push(Shape::Line { points: vec![at + Point::xy(-3.0, 3.0), at + Point::xy(3.0, -3.0)], color: c, width: 1.0 });
+                        },
+                        "text" => {
+                            let at = t.apply(attr_num(&attributes, "x"), attr_num(&attributes, "y"));
+                            text = Some((String::new(), at, st.clone()));
+                        },
+                        _ => {}
+                    }
+                    styles.push(st);
+                },
+                XmlEvent::Characters(s) => {
+                    if hidden == 0 {
+                        if let Some((ref mut body, _, _)) = text { body.push_str(&s); }
+                    }
+                },
+                XmlEvent::Whitespace(s) => {
+                    if let Some((ref mut body, _, _)) = text { if !body.is_empty() { body.push_str(&s); } }
+                },
+                XmlEvent::EndElement { name } => {
+                    styles.pop();
+                    if hidden > 0 {
+                        hidden -= 1;
+                    } else if name.local_name == "text" {
+                        if let Some((body, at, st)) = text.take() {
+                            let body = body.trim();
+                            if !body.is_empty() {
+                                shapes.push(Shape::Text {
+                                    at, text: String::from(body), anchor: st.anchor,
+                                    size: st.font_size * st.transform.scale(),
+                                    color: st.fill.unwrap_or(st.color)
+                                });
+                            }
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        match size {
+            Some((w, h)) if w > 0.0 && h > 0.0 => Ok(Figure::new(w, h, shapes)),
+            _ => Err(From::from("svg has no size"))
+        }
+    }
+
+    /// how much the figure is shrunk to fit in `width`. Figures are never drawn larger than they were made
+    fn scale_for(&self, width: f32) -> f32 {
+        (width / self.width).min(1.0).max(0.1)
+    }
+
+    /// the area the figure took up when it was last drawn
+    pub fn bounds(&self) -> Rect {
+        Rect::wh(self.width * self.scale, self.height * self.scale)
+    }
+
+    /// Draw the figure with its top left corner at `p`, scaled down if need be to fit in `width`.
+    /// Text is laid out and fills are split into rows again whenever the scale changes, so that
+    /// they stay in proportion
+    pub fn draw(&mut self, p: Point, width: f32, rx: &mut RenderContext, fonts: &Fonts) {
+        let scale = self.scale_for(width);
+        // on whole pixels, so that the rows of the fills line up with the screen's
+        let p = Point::xy(p.x.round(), p.y.round());
+        if self.layouts.is_none() || scale != self.scale {
+            self.scale = scale;
+            let whole = Rect::wh(self.width * scale, self.height * scale);
+            self.spans = self.shapes.iter().map(|s| match *s {
+                Shape::Fill { ref points, .. } => {
+                    let pts: Vec<Point> = points.iter().map(|q| Point::xy(q.x * scale, q.y * scale)).collect();
+                    fill_spans(&pts, whole)
+                },
+                _ => Vec::new()
+            }).collect();
+            self.layouts = Some(self.shapes.iter().map(|s| match *s {
+                Shape::Text { ref text, size, .. } => {
+                    let mut ly = rx.new_text_layout(text, &fonts.text, self.width * scale, 256.0).ok()?;
+                    ly.size_range(0..text.encode_utf16().count() as u32, (size * scale).max(4.0));
+                    Some(ly)
+                },
+                _ => None
+            }).collect());
+        }
+        let at = |q: Point| Point::xy(p.x + q.x * scale, p.y + q.y * scale);
+        // anything reaching past the edges, as a zoomed in surface can, is cut off there
+        let area = Rect::xywh(p.x, p.y, self.width * scale, self.height * scale);
+        let (lo, hi) = ((area.x, area.y), (area.x + area.w, area.y + area.h));
+        // gnuplot draws in dark colors meant for paper
+        rx.set_color(Color::rgb(1.0, 1.0, 1.0));
+        rx.fill_rect(area);
+        for ((s, ly), spans) in self.shapes.iter().zip(self.layouts.as_ref().unwrap()).zip(&self.spans) {
+            match *s {
+                Shape::Line { ref points, color, width } => {
+                    rx.set_color(color.color());
+                    for w in points.windows(2) {
+                        let (a, b) = (at(w[0]), at(w[1]));
+                        if let Some((a, b)) = clip((a.x, a.y), (b.x, b.y), lo, hi) {
+                            rx.draw_line(Point::xy(a.0, a.1), Point::xy(b.0, b.1), (width * scale).max(1.0));
+                        }
+                    }
+                },
+                Shape::Fill { color, .. } => {
+                    rx.set_color(color.color());
+                    for r in spans {
+                        rx.fill_rect(Rect::xywh(p.x + r.x, p.y + r.y, r.w, r.h));
+                    }
+                },
+                Shape::Text { at: q, anchor, color, .. } => {
+                    if let Some(ref ly) = *ly {
+                        let b = ly.bounds();
+                        let q = at(q);
+                        if !area.contains(q) { continue; }
+                        let x = match anchor {
+                            Anchor::Start => q.x,
+                            Anchor::Middle => q.x - b.w/2.0,
+                            Anchor::End => q.x - b.w
+                        };
+                        // SVG text sits on its baseline, which is near the bottom of the layout
+                        rx.set_color(color.color());
+                        rx.draw_text_layout(Point::xy(x, q.y - b.h * 0.8), ly);
+                    }
+                }
+            }
+        }
+        rx.set_color(Color::rgb(0.8, 0.75, 0.7));
+    }
+}
+
+/// One line of a plot, in data coordinates, broken into pieces where the function has no real value
+pub struct Curve {
+    pub label: String,
+    pub pieces: Vec<Vec<(f32, f32)>>
+}
+
+/// A plot of some curves as sampled by Maxima, before it is laid out as a figure
+pub struct Plot2d {
+    pub title: Option<String>,
+    pub xlabel: Option<String>,
+    pub ylabel: Option<String>,
+    pub x_range: Option<(f32, f32)>,
+    pub y_range: Option<(f32, f32)>,
+    /// labels for the curves, in place of the functions they plot; none at all hides the legend
+    pub legend: Option<Vec<String>>,
+    pub curves: Vec<Curve>
+}
+
+/// the colors curves are drawn in, in turn
+const CURVE_COLORS: [Rgb; 6] = [
+    Rgb(0.0, 0.38, 0.68), Rgb(0.85, 0.2, 0.15), Rgb(0.1, 0.55, 0.25),
+    Rgb(0.55, 0.25, 0.6), Rgb(0.9, 0.55, 0.0), Rgb(0.2, 0.2, 0.2)
+];
+
+/// size of the figure a plot is laid out in, and the space around the axes for labels
+const PLOT_W: f32 = 600.0;
+const PLOT_H: f32 = 400.0;
+const MARGIN_LEFT: f32 = 64.0;
+const MARGIN_RIGHT: f32 = 24.0;
+const MARGIN_TOP: f32 = 36.0;
+const MARGIN_BOTTOM: f32 = 52.0;
+
+/// about five round-numbered ticks covering `lo..hi`, with how many decimals they need
+fn ticks(lo: f32, hi: f32) -> (Vec<f32>, usize) {
+    // in f64, so that a narrow range far from zero still has room between its ticks
+    let (lo, hi) = (lo as f64, hi as f64);
+    let rough = (hi - lo) / 5.0;
+    let mag = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].iter().map(|m| m * mag).find(|&s| s >= rough).unwrap_or(10.0 * mag);
+    if !(step > 0.0) || !step.is_finite() { return (Vec::new(), 0); }
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let first = (lo / step).ceil();
+    let count = (hi / step + 1e-3).floor() - first + 1.0;
+    if !(count >= 1.0) { return (Vec::new(), decimals); }
+    let out = (0..count.min(50.0) as usize).map(|i| {
+        let t = (first + i as f64) * step;
+        // keep -0 from showing up
+        (if t.abs() < step * 1e-3 { 0.0 } else { t }) as f32
+    }).collect();
+    (out, decimals)
+}
+
+/// the part of the segment `a`-`b` inside the box from `lo` to `hi`, if any
+pub fn clip(a: (f32, f32), b: (f32, f32), lo: (f32, f32), hi: (f32, f32)) -> Option<((f32, f32), (f32, f32))> {
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    for &(p, q) in &[(-dx, a.0 - lo.0), (dx, hi.0 - a.0), (-dy, a.1 - lo.1), (dy, hi.1 - a.1)] {
+        if p == 0.0 {
+            if q < 0.0 { return None; }
+        } else {
+            let r = q / p;
+            if p < 0.0 { t0 = t0.max(r); } else { t1 = t1.min(r); }
+        }
+    }
+    if t0 > t1 { return None; }
+    Some(((a.0 + t0*dx, a.1 + t0*dy), (a.0 + t1*dx, a.1 + t1*dy)))
+}
+
+/// add the points gathered so far as a curve, if there are enough to draw, and start over
+fn end_line(run: &mut Vec<Point>, shapes: &mut Vec<Shape>, color: Rgb) {
+    if run.len() > 1 {
+        shapes.push(Shape::Line { points: run.split_off(0), color, width: 1.5 });
+    }
+    run.clear();
+}
+
+impl Plot2d {
+    /// read what the replacement `plot2d` in `PLOT_LISP` prints between <plot></plot>
+    pub fn parse(text: &str) -> Plot2d {
+        let mut plot = Plot2d { title: None, xlabel: None, ylabel: None, x_range: None, y_range: None, legend: Some(Vec::new()), curves: Vec::new() };
+        for line in text.lines().map(|l| l.trim()) {
+            if line.starts_with("p ") {
+                let v: Vec<f32> = line[2..].split_whitespace().filter_map(|n| n.parse().ok()).collect();
+                if let (Some(c), 2) = (plot.curves.last_mut(), v.len()) {
+                    if !v[0].is_finite() || !v[1].is_finite() { continue; }
+                    if c.pieces.is_empty() { c.pieces.push(Vec::new()); }
+                    c.pieces.last_mut().unwrap().push((v[0], v[1]));
+                }
+                continue;
+            }
+            if line == "gap" {
+                if let Some(c) = plot.curves.last_mut() {
+                    if c.pieces.last().map_or(false, |p| !p.is_empty()) { c.pieces.push(Vec::new()); }
+                }
+                continue;
+            }
+            let mut parts = line.split('|');
+            let key = parts.next().unwrap_or("");
+            let rest: Vec<&str> = parts.collect();
+            let range = || match (rest.get(0).and_then(|v| v.parse().ok()), rest.get(1).and_then(|v| v.parse().ok())) {
+                (Some(a), Some(b)) if a < b => Some((a, b)),
+                _ => None
+            };
+            match key {
+                "curve" => plot.curves.push(Curve { label: rest.join("|"), pieces: Vec::new() }),
+                "title" => plot.title = rest.get(0).map(|s| String::from(*s)),
+                "xlabel" => plot.xlabel = rest.get(0).map(|s| String::from(*s)),
+                "ylabel" => plot.ylabel = rest.get(0).map(|s| String::from(*s)),
+                "x" => plot.x_range = range(),
+                "y" => plot.y_range = range(),
+                "legend" if rest == ["false"] => plot.legend = None,
+                "legend" => plot.legend = Some(rest.iter().map(|s| String::from(*s)).collect()),
+                _ => {}
+            }
+        }
+        plot
+    }
+
+    /// the range covered by every point, along one axis
+    fn extent<F: Fn(&(f32, f32)) -> f32>(&self, f: F) -> Option<(f32, f32)> {
+        let vals = self.curves.iter().flat_map(|c| c.pieces.iter()).flat_map(|p| p.iter()).map(f);
+        vals.fold(None, |r, v| Some(match r {
+            Some((lo, hi)) => (v.min(lo), v.max(hi)),
+            None => (v, v)
+        }))
+    }
+
+    /// Lay the plot out with a box of axes, ticks with grid lines, the curves clipped to the box,
+    /// and the labels, title and legend around them
+    pub fn figure(&self) -> Figure {
+        let widen = |(lo, hi): (f32, f32)| if hi - lo > 1e-6 * lo.abs().max(1.0) { (lo, hi) } else { (lo - 1.0, hi + 1.0) };
+        let (x0, x1) = widen(self.x_range.or_else(|| self.extent(|p| p.0)).unwrap_or((-1.0, 1.0)));
+        let (y0, y1) = widen(self.y_range.or_else(|| self.extent(|p| p.1)).unwrap_or((-1.0, 1.0)));
+        let (left, right) = (MARGIN_LEFT, PLOT_W - MARGIN_RIGHT);
+        let (top, bottom) = (MARGIN_TOP, PLOT_H - MARGIN_BOTTOM);
+        let to_fig = |(x, y): (f32, f32)| Point::xy(left + (x - x0) / (x1 - x0) * (right - left),
+                                                    bottom - (y - y0) / (y1 - y0) * (bottom - top));
+        let black = Rgb(0.0, 0.0, 0.0);
+        let grid = Rgb(0.88, 0.88, 0.88);
+        let mut shapes = Vec::new();
+        let text = |at: Point, s: &str, anchor: Anchor| Shape::Text { at, text: String::from(s), anchor, size: 12.0, color: black };
+
+        let (xt, xd) = ticks(x0, x1);
+        for &t in &xt {
+            let p = to_fig((t, y0));
+            shapes.push(Shape::Line { points: vec![Point::xy(p.x, top), Point::xy(p.x, bottom)], color: grid, width: 1.0 });
+            shapes.push(Shape::Line { points: vec![p, p - Point::y(6.0)], color: black, width: 1.0 });
+            shapes.push(text(p + Point::y(16.0), &format!("{:.*}", xd, t), Anchor::Middle));
+        }
+        let (yt, yd) = ticks(y0, y1);
+        for &t in &yt {
+            let p = to_fig((x0, t));
+            shapes.push(Shape::Line { points: vec![Point::xy(left, p.y), Point::xy(right, p.y)], color: grid, width: 1.0 });
+            shapes.push(Shape::Line { points: vec![p, p + Point::x(6.0)], color: black, width: 1.0 });
+            shapes.push(text(p + Point::xy(-6.0, 4.0), &format!("{:.*}", yd, t), Anchor::End));
+        }
+        shapes.push(Shape::Line {
+            points: vec![Point::xy(left, top), Point::xy(right, top), Point::xy(right, bottom), Point::xy(left, bottom), Point::xy(left, top)],
+            color: black, width: 1.0
+        });
+
+        for (i, c) in self.curves.iter().enumerate() {
+            let color = CURVE_COLORS[i % CURVE_COLORS.len()];
+            for piece in &c.pieces {
+                // pieces of line that leave the box are cut where they cross it
+                let mut run: Vec<Point> = Vec::new();
+                for w in piece.windows(2) {
+                    match clip(w[0], w[1], (x0, y0), (x1, y1)) {
+                        Some((a, b)) => {
+                            if run.is_empty() { run.push(to_fig(a)); }
+                            run.push(to_fig(b));
+                            if b != w[1] { end_line(&mut run, &mut shapes, color); }
+                        },
+                        None => end_line(&mut run, &mut shapes, color)
+                    }
+                }
+                end_line(&mut run, &mut shapes, color);
+                if piece.len() == 1 {
+                    // a lone point, as from discrete data, is marked with a cross
+                    let p = to_fig(piece[0]);
+                    shapes.push(Shape::Line { points: vec![p - Point::xy(3.0, 3.0), p + Point::xy(3.0, 3.0)], color, width: 1.0 });
+                    shapes.push(Shape::Line { points: vec![p + Point::xy(-3.0, 3.0), p + Point::xy(3.0, -3.0)], color, width: 1.0 });
+                }
+            }
+        }
+
+        if let Some(ref legend) = self.legend {
+            let mut y = top + 16.0;
+            for (i, c) in self.curves.iter().enumerate() {
+                let label = legend.get(i).map(|s| s.as_str()).unwrap_or(&c.label);
+                let color = CURVE_COLORS[i % CURVE_COLORS.len()];
+                shapes.push(Shape::Line { points: vec![Point::xy(right - 36.0, y - 4.0), Point::xy(right - 8.0, y - 4.0)], color, width: 1.5 });
+                shapes.push(text(Point::xy(right - 42.0, y), label, Anchor::End));
+                y += 16.0;
+            }
+        }
+        if let Some(ref t) = self.title {
+            shapes.push(Shape::Text { at: Point::xy(PLOT_W / 2.0, 24.0), text: t.clone(), anchor: Anchor::Middle, size: 14.0, color: black });
+        }
+        if let Some(ref l) = self.xlabel {
+            shapes.push(text(Point::xy((left + right) / 2.0, PLOT_H - 12.0), l, Anchor::Middle));
+        }
+        if let Some(ref l) = self.ylabel {
+            shapes.push(text(Point::xy(8.0, top - 12.0), l, Anchor::Start));
+        }
+        Figure::new(PLOT_W, PLOT_H, shapes)
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl Figure {
+    /// write the figure as a standalone SVG document
+    pub fn to_svg(&self) -> String {
+        let mut out = String::new();
+        let pts = |points: &[Point]| points.iter().map(|p| format!("{:.2},{:.2}", p.x, p.y)).collect::<Vec<_>>().join(" ");
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>").unwrap();
+        writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+                 w = self.width, h = self.height).unwrap();
+        writeln!(out, "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>", self.width, self.height).unwrap();
+        for s in &self.shapes {
+            match *s {
+                Shape::Line { ref points, color, width } => writeln!(out,
+                    "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" points=\"{}\"/>", color.svg(), width, pts(points)),
+                Shape::Fill { ref points, color } => writeln!(out,
+                    "<polygon fill=\"{}\" stroke=\"none\" points=\"{}\"/>", color.svg(), pts(points)),
+                Shape::Text { at, ref text, anchor, size, color } => writeln!(out,
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-family=\"sans-serif\" font-size=\"{}\" text-anchor=\"{}\" fill=\"{}\">{}</text>",
+                    at.x, at.y, size, match anchor { Anchor::Start => "start", Anchor::Middle => "middle", Anchor::End => "end" },
+                    color.svg(), escape_xml(text))
+            }.unwrap();
+        }
+        writeln!(out, "</svg>").unwrap();
+        out
+    }
+}
+
+/// The part of a polygon inside `area` as one rectangle for each row of pixels it covers, since
+/// the render context only fills rectangles
+fn fill_spans(points: &[Point], area: Rect) -> Vec<Rect> {
+    let mut out = Vec::new();
+    if points.len() < 3 { return out; }
+    let top = points.iter().map(|p| p.y).fold(::std::f32::INFINITY, f32::min).floor().max(area.y.floor());
+    let bottom = points.iter().map(|p| p.y).fold(::std::f32::NEG_INFINITY, f32::max).ceil().min(area.y + area.h);
+    let mut crossings = Vec::new();
+    let mut y = top;
+    while y < bottom {
+        let sy = y + 0.5;
+        crossings.clear();
+        for i in 0..points.len() {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            if (a.y <= sy) != (b.y <= sy) {
+                crossings.push(a.x + (sy - a.y) / (b.y - a.y) * (b.x - a.x));
+            }
+        }
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+        for span in crossings.chunks(2) {
+            let (a, b) = (span[0].max(area.x), span.get(1).cloned().unwrap_or(0.0).min(area.x + area.w));
+            if span.len() == 2 && b > a {
+                out.push(Rect::xywh(a, y, b - a, 1.0));
+            }
+        }
+        y += 1.0;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use runic::{Point, Rect};
+    use super::{ticks, clip, parse_path, fill_spans, Plot2d, Transform};
+
+    fn near(p: Point, x: f32, y: f32) -> bool {
+        (p.x - x).abs() < 1e-4 && (p.y - y).abs() < 1e-4
+    }
+
+    #[test]
+    fn plot_output() {
+        // as `plot2d([sqrt(x), x], [x, -1, 4], [y, -1, 1], [title, "roots"])` prints it
+        let text = "y|-1|1\ntitle|roots\nx|-1.0|4.0\ncurve|sqrt(x)\ngap\ngap\np 0.0 0.0\np 1.0 1.0\ngap\n\
+                    p 3.0 1.7320508\np 4.0 2.0\ncurve|x\np 0.0 0.0\np 4.0 4.0\n";
+        let plot = Plot2d::parse(text);
+        assert_eq!(plot.title, Some(String::from("roots")));
+        // the range asked for, even though the square root is only sampled from 0
+        assert_eq!(plot.x_range, Some((-1.0, 4.0)));
+        assert_eq!(plot.y_range, Some((-1.0, 1.0)));
+        assert_eq!(plot.curves.len(), 2);
+        assert_eq!(plot.curves[0].label, "sqrt(x)");
+        // gaps before the first point don't leave empty pieces, and one between points splits the curve
+        assert_eq!(plot.curves[0].pieces, vec![vec![(0.0, 0.0), (1.0, 1.0)], vec![(3.0, 1.7320508), (4.0, 2.0)]]);
+        assert_eq!(plot.curves[1].pieces, vec![vec![(0.0, 0.0), (4.0, 4.0)]]);
+        // a range that runs backwards is ignored, and `legend|false` hides the legend
+        let plot = Plot2d::parse("x|2|1\nlegend|false\n");
+        assert_eq!(plot.x_range, None);
+        assert!(plot.legend.is_none());
+    }
+
+    #[test]
+    fn transforms() {
+        // the rotation applies first, then the translation
+        let t = Transform::parse("translate(10,20) rotate(90)");
+        assert!(near(t.apply(1.0, 0.0), 10.0, 21.0));
+        let t = Transform::parse("scale(2) translate(1 1)");
+        assert!(near(t.apply(0.0, 0.0), 2.0, 2.0));
+        let t = Transform::parse("matrix(1,0,0,-1,5,6)");
+        assert!(near(t.apply(1.0, 1.0), 6.0, 5.0));
+        assert!(near(Transform::parse("skewX(30)").apply(3.0, 4.0), 3.0, 4.0));
+    }
+
+    #[test]
+    fn paths() {
+        let paths = parse_path("m 10 10 l 5 0 0 5 z M 0 0 h 3 v 4 H 1");
+        assert_eq!(paths, vec![
+            (vec![(10.0, 10.0), (15.0, 10.0), (15.0, 15.0), (10.0, 10.0)], true),
+            (vec![(0.0, 0.0), (3.0, 0.0), (3.0, 4.0), (1.0, 4.0)], false)
+        ]);
+        // pairs after a relative move are relative lines, and a curve is replaced by a line to its end
+        let paths = parse_path("m1,1 2,2c1 1 2 2 3 3");
+        assert_eq!(paths, vec![(vec![(1.0, 1.0), (3.0, 3.0), (6.0, 6.0)], false)]);
+        // after a close, relative commands carry on from the start of the subpath
+        let paths = parse_path("M5 5 L 9 5 Z m 1 1 l 1 0");
+        assert_eq!(paths[1], (vec![(6.0, 6.0), (7.0, 6.0)], false));
+    }
+
+    #[test]
+    fn clipping() {
+        let (lo, hi) = ((0.0, 0.0), (1.0, 1.0));
+        assert_eq!(clip((-1.0, 0.5), (2.0, 0.5), lo, hi), Some(((0.0, 0.5), (1.0, 0.5))));
+        assert_eq!(clip((0.25, 0.25), (0.75, 0.5), lo, hi), Some(((0.25, 0.25), (0.75, 0.5))));
+        assert_eq!(clip((2.0, 2.0), (3.0, 3.0), lo, hi), None);
+        // along an edge's line, but outside
+        assert_eq!(clip((0.0, 2.0), (1.0, 2.0), lo, hi), None);
+        // past a corner without crossing the box
+        assert_eq!(clip((0.5, 1.5), (1.5, 0.5), (0.0, 0.0), (0.9, 0.9)), None);
+    }
+
+    #[test]
+    fn fills() {
+        let square = [Point::xy(1.0, 0.0), Point::xy(3.0, 0.0), Point::xy(3.0, 2.0), Point::xy(1.0, 2.0)];
+        let rows = fill_spans(&square, Rect::wh(10.0, 10.0));
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|r| r.x == 1.0 && r.w == 2.0 && r.h == 1.0));
+        // cut off at the edge of the area
+        let rows = fill_spans(&square, Rect::wh(2.0, 1.0));
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].x, rows[0].w), (1.0, 1.0));
+    }
+
+    #[test]
+    fn tick_steps() {
+        assert_eq!(ticks(0.0, 1.0), (vec![0.0, 0.2, 0.4, 0.6, 0.8, 1.0], 1));
+        assert_eq!(ticks(-10.0, 10.0).0, vec![-10.0, -5.0, 0.0, 5.0, 10.0]);
+        // steps too small to tell apart in f32 still end
+        let (t, _) = ticks(9999999.0, 10000001.0);
+        assert!(!t.is_empty() && t.len() <= 50);
+        assert!(ticks(1.0, 1.0).0.is_empty());
+        assert!(ticks(0.0, ::std::f32::INFINITY).0.is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+/// A request made of Maxima on the app's own behalf rather than from a cell. Each one runs as a
+/// `:lisp` form, so it doesn't use up an input label or disturb `%`, and prints its reply
+/// between `<reply>` tags
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Query {
+    /// every symbol Maxima knows, one per line, each marked `o` for an option variable or `s` otherwise
+    Symbols,
+    /// user defined functions (`f`) and values (`v`)
+    Definitions,
+    /// the documentation for a topic, exactly as `describe` prints it
+    Describe(String),
+    /// every value (`v`), function (`f`), array (`a`) and dependency (`d`), each as
+    /// `<item kind name>` followed by MathML for its value or definition and `</item>`
+    Inspect
+}
+
+impl Query {
+    /// the line to send to Maxima
+    pub fn command(&self) -> String {
+        let body = match *self {
+            Query::Symbols => String::from(
+                "(dolist (s (cdr ($apropos \"\"))) \
+                   (format t \"~a ~a~%\" (if (and (boundp s) (not (member s (cdr $values)))) \"o\" \"s\") ($sconcat s)))"),
+            Query::Definitions => String::from(
+                "(dolist (f (cdr $functions)) (format t \"f ~a~%\" ($sconcat (caar f)))) \
+                 (dolist (v (cdr $values)) (format t \"v ~a~%\" ($sconcat v)))"),
+            // topics come from identifiers, so there's nothing in them to escape
+            Query::Describe(ref topic) => format!("(meval '(($describe) \"{}\" $exact))", topic),
+            Query::Inspect => String::from(
+                "(dolist (v (cdr $values)) \
+                   (format t \"<item v ~a>\" ($sconcat v)) (mathml1 (symbol-value v)) (format t \"</item>~%\")) \
+                 (dolist (f (cdr $functions)) \
+                   (format t \"<item f ~a>\" ($sconcat (caar f))) (mathml1 (meval (list '($fundef) (caar f)))) (format t \"</item>~%\")) \
+                 (dolist (a (cdr $arrays)) \
+                   (format t \"<item a ~a>\" ($sconcat a)) (mathml1 (meval (list '($arrayinfo) a))) (format t \"</item>~%\")) \
+                 (dolist (d (cdr $dependencies)) \
+                   (format t \"<item d ~a>\" ($sconcat (caar d))) (mathml1 d) (format t \"</item>~%\"))")
+        };
+        format!(":lisp (progn (princ \"<reply>\") (terpri) {} (princ \"</reply>\") (terpri) (values))\n", body)
+    }
+}
@@ -0,0 +1,184 @@
+use std::io::{self, Write, BufRead, BufReader};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::env;
+
+/// how many inputs are kept
+const LIMIT: usize = 2000;
+
+/// Where rMaxima keeps its files: `%APPDATA%\rmaxima` on Windows, otherwise
+/// `$XDG_DATA_HOME/rmaxima` or `~/.local/share/rmaxima`
+pub fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".local").join("share")))
+    };
+    base.map(|b| b.join("rmaxima"))
+}
+
+/// inputs are stored one per line, so line breaks and backslashes in them are escaped
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some(c) => out.push(c),
+                None => {}
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Every input sent to Maxima, oldest first, kept in a file so that it carries over between sessions
+pub struct InputHistory {
+    entries: Vec<String>,
+    path: Option<PathBuf>
+}
+
+impl InputHistory {
+    /// Read the history from `path`, which is created when the first input is added. With no
+    /// path, the history only lasts as long as the session
+    pub fn open(path: Option<PathBuf>) -> io::Result<InputHistory> {
+        let mut entries = Vec::new();
+        if let Some(ref p) = path {
+            if p.exists() {
+                for line in BufReader::new(File::open(p)?).lines() {
+                    entries.push(unescape(&line?));
+                }
+            }
+        }
+        let excess = entries.len().saturating_sub(LIMIT);
+        entries.drain(..excess);
+        Ok(InputHistory { entries, path })
+    }
+
+    pub fn get(&self, i: usize) -> &str {
+        &self.entries[i]
+    }
+
+    /// remember an input, unless it is the same as the last one
+    pub fn add(&mut self, input: &str) -> io::Result<()> {
+        let input = input.trim();
+        if input.is_empty() || self.entries.last().map_or(false, |e| e == input) {
+            return Ok(());
+        }
+        self.entries.push(String::from(input));
+        if let Some(ref p) = self.path {
+            if let Some(dir) = p.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut f = OpenOptions::new().create(true).append(true).open(p)?;
+            writeln!(f, "{}", escape(input))?;
+        }
+        if self.entries.len() > 2*LIMIT {
+            // keep the file from growing without end, now and then
+            let excess = self.entries.len() - LIMIT;
+            self.entries.drain(..excess);
+            self.rewrite()?;
+        }
+        Ok(())
+    }
+
+    fn rewrite(&self) -> io::Result<()> {
+        if let Some(ref p) = self.path {
+            let mut f = File::create(p)?;
+            for e in &self.entries {
+                writeln!(f, "{}", escape(e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the newest entry before `before` (or the newest of all) that starts with `prefix`
+    pub fn older(&self, before: Option<usize>, prefix: &str) -> Option<usize> {
+        let end = before.unwrap_or(self.entries.len());
+        self.entries[..end].iter().rposition(|e| e.starts_with(prefix))
+    }
+
+    /// the oldest entry after `after` that starts with `prefix`
+    pub fn newer(&self, after: usize, prefix: &str) -> Option<usize> {
+        self.entries.iter().enumerate().skip(after+1).find(|&(_, e)| e.starts_with(prefix)).map(|(i, _)| i)
+    }
+
+    /// the newest entry before `before` (or the newest of all) containing `query`
+    pub fn search(&self, query: &str, before: Option<usize>) -> Option<usize> {
+        let end = before.unwrap_or(self.entries.len());
+        self.entries[..end].iter().rposition(|e| e.contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(inputs: &[&str]) -> InputHistory {
+        let mut h = InputHistory::open(None).unwrap();
+        for i in inputs { h.add(i).unwrap(); }
+        h
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        for s in &["plain", "two\nlines", "a\\nb", "back\\slash\\", "\\\n\\", ""] {
+            assert_eq!(unescape(&escape(s)), *s);
+            assert!(!escape(s).contains('\n'));
+        }
+    }
+
+    #[test]
+    fn history_file_keeps_inputs_unchanged() {
+        let path = env::temp_dir().join(format!("rmaxima-test-{}-history", ::std::process::id()));
+        let inputs = ["f(x) := block(\n  x^2)", "print(\"a\\nb\")", "s: \"C:\\\\dir\\\\\""];
+        {
+            let mut h = InputHistory::open(Some(path.clone())).unwrap();
+            for i in &inputs { h.add(i).unwrap(); }
+        }
+        let h = InputHistory::open(Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+        let h = h.unwrap();
+        assert_eq!((0..3).map(|i| h.get(i)).collect::<Vec<_>>(), inputs);
+    }
+
+    #[test]
+    fn repeats_and_blanks_are_not_added() {
+        let h = history(&["a", "a", "  ", "b", "a"]);
+        assert_eq!(h.older(None, ""), Some(2));
+        assert_eq!(h.older(Some(2), ""), Some(1));
+        assert_eq!(h.older(Some(1), ""), Some(0));
+    }
+
+    #[test]
+    fn navigation_stops_at_both_ends() {
+        let h = history(&["x: 1", "y: 2", "x + y"]);
+        assert_eq!(h.older(None, ""), Some(2));
+        assert_eq!(h.older(Some(0), ""), None);
+        assert_eq!(h.newer(0, ""), Some(1));
+        assert_eq!(h.newer(2, ""), None);
+        // only entries starting with the prefix are stepped through
+        assert_eq!(h.older(None, "x"), Some(2));
+        assert_eq!(h.older(Some(2), "x"), Some(0));
+        assert_eq!(h.newer(0, "x"), Some(2));
+        assert_eq!(h.older(None, "z"), None);
+        assert_eq!(InputHistory::open(None).unwrap().older(None, ""), None);
+    }
+
+    #[test]
+    fn search_finds_the_newest_match() {
+        let h = history(&["integrate(x, x)", "diff(x^2, x)", "integrate(sin(x), x)"]);
+        assert_eq!(h.search("integrate", None), Some(2));
+        assert_eq!(h.search("integrate", Some(2)), Some(0));
+        assert_eq!(h.search("integrate", Some(0)), None);
+        assert_eq!(h.search("limit", None), None);
+    }
+}
@@ -0,0 +1,247 @@
+use runic::*;
+
+use plot::{Figure, Shape, Anchor, Rgb};
+
+/// Lisp run at startup after `plot::PLOT_LISP`, whose option printer it shares, which defines a
+/// `plot3d` that samples the function over a grid and prints it between <surface></surface>.
+/// Inside are options as `name|value|...`, `label|` with the function, `axes|` with the names of
+/// the variables, `grid|n|x0|x1|y0|y1` and then one `r` line of z values for each y, with `nan`
+/// where the function has no real value
+pub const SURFACE_LISP: &'static str = "\
+(defun rm-plot-grid (fun xr yr n) \
+  (let ((ff (coerce-float-fun fun (list '(mlist) (cadr xr) (cadr yr)))) \
+        (x0 ($float (caddr xr))) (x1 ($float (cadddr xr))) \
+        (y0 ($float (caddr yr))) (y1 ($float (cadddr yr)))) \
+    (format t \"grid|~a|~f|~f|~f|~f~%\" n x0 x1 y0 y1) \
+    (dotimes (j (1+ n)) \
+      (princ \"r\") \
+      (dotimes (i (1+ n)) \
+        (let ((z (ignore-errors (funcall ff (+ x0 (* i (/ (- x1 x0) n))) (+ y0 (* j (/ (- y1 y0) n))))))) \
+          (if (realp z) (format t \" ~f\" z) (princ \" nan\")))) \
+      (terpri)))) \
+(defun rm-plot3d (fun xr yr &rest options) \
+  (princ \"<surface>\") (terpri) \
+  (unwind-protect \
+    (progn \
+      (mapc #'rm-plot-option (remove-if-not #'$listp options)) \
+      (format t \"label|~a~%axes|~a|~a~%\" ($string fun) ($sconcat (cadr xr)) ($sconcat (cadr yr))) \
+      (rm-plot-grid fun xr yr 36)) \
+    (princ \"</surface>\") (terpri)) \
+  '$done)";
+
+/// size of the figure a surface is drawn in
+const VIEW_W: f32 = 600.0;
+const VIEW_H: f32 = 450.0;
+
+/// A surface z = f(x, y) sampled by Maxima over a grid, with the direction it is seen from
+pub struct Surface {
+    pub label: String,
+    pub title: Option<String>,
+    /// names of the two variables, for the axes
+    pub axes: (String, String),
+    pub x_range: (f32, f32),
+    pub y_range: (f32, f32),
+    /// z for each point of the grid, row by row along y, or none where the function isn't real
+    pub z: Vec<Vec<Option<f32>>>,
+    /// rotation about the vertical axis and tilt towards the viewer, in radians
+    pub azimuth: f32,
+    pub elevation: f32,
+    pub zoom: f32
+}
+
+/// a point in the unit cube the surface is fitted into, turned to face the viewer
+#[derive(Copy, Clone)]
+struct Projected {
+    screen: Point,
+    /// how far away the point is, for drawing the back of the surface first
+    depth: f32,
+    /// the point in view space, for shading
+    view: [f32; 3]
+}
+
+/// blue through cyan, green and yellow to red as `t` goes from 0 to 1
+fn height_color(t: f32) -> Rgb {
+    let stops = [Rgb(0.15, 0.25, 0.8), Rgb(0.1, 0.7, 0.8), Rgb(0.3, 0.75, 0.3), Rgb(0.95, 0.85, 0.2), Rgb(0.85, 0.2, 0.15)];
+    let t = t.max(0.0).min(1.0) * (stops.len() - 1) as f32;
+    let i = (t.floor() as usize).min(stops.len() - 2);
+    let f = t - i as f32;
+    let (a, b) = (stops[i], stops[i+1]);
+    Rgb(a.0 + (b.0 - a.0)*f, a.1 + (b.1 - a.1)*f, a.2 + (b.2 - a.2)*f)
+}
+
+/// a number rounded for a label
+fn short(v: f32) -> String {
+    format!("{}", (v * 1000.0).round() / 1000.0)
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1]*b[2] - a[2]*b[1], a[2]*b[0] - a[0]*b[2], a[0]*b[1] - a[1]*b[0]]
+}
+
+impl Surface {
+    /// read what the replacement `plot3d` in `SURFACE_LISP` prints between <surface></surface>
+    pub fn parse(text: &str) -> Option<Surface> {
+        let mut s = Surface {
+            label: String::new(), title: None, axes: (String::from("x"), String::from("y")),
+            x_range: (0.0, 1.0), y_range: (0.0, 1.0), z: Vec::new(),
+            azimuth: 0.6, elevation: 0.5, zoom: 1.0
+        };
+        for line in text.lines().map(|l| l.trim()) {
+            if line.starts_with('r') && !line.contains('|') {
+                s.z.push(line[1..].split_whitespace().map(|v| v.parse().ok().filter(|z: &f32| z.is_finite())).collect());
+                continue;
+            }
+            let parts: Vec<&str> = line.split('|').collect();
+            let num = |i: usize| parts.get(i).and_then(|v| v.parse::<f32>().ok());
+            match parts[0] {
+                "label" => s.label = parts[1..].join("|"),
+                "title" => s.title = parts.get(1).map(|t| String::from(*t)),
+                "axes" if parts.len() == 3 => s.axes = (String::from(parts[1]), String::from(parts[2])),
+                "grid" => {
+                    s.x_range = (num(2)?, num(3)?);
+                    s.y_range = (num(4)?, num(5)?);
+                },
+                _ => {}
+            }
+        }
+        if s.z.len() < 2 || s.z.iter().any(|r| r.len() != s.z[0].len()) || s.z[0].len() < 2 {
+            return None;
+        }
+        Some(s)
+    }
+
+    /// turn the view by a mouse drag of `dx`, `dy` pixels
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.azimuth += dx * 0.01;
+        self.elevation = (self.elevation + dy * 0.01).max(-1.5).min(1.5);
+    }
+
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).max(0.3).min(4.0);
+    }
+
+    fn z_range(&self) -> (f32, f32) {
+        let zs = self.z.iter().flat_map(|r| r.iter()).filter_map(|&z| z);
+        let (lo, hi) = zs.fold((::std::f32::INFINITY, ::std::f32::NEG_INFINITY), |(lo, hi), z| (lo.min(z), hi.max(z)));
+        if lo > hi { (-1.0, 1.0) } else if hi - lo < 1e-6 { (lo - 1.0, hi + 1.0) } else { (lo, hi) }
+    }
+
+    /// place a point of the cube from -1 to 1 (with z squashed a little) in the view
+    fn project(&self, p: [f32; 3]) -> Projected {
+        let (sa, ca) = self.azimuth.sin_cos();
+        let (se, ce) = self.elevation.sin_cos();
+        let x1 = p[0]*ca - p[1]*sa;
+        let y1 = p[0]*sa + p[1]*ca;
+        let depth = y1*ce - p[2]*se;
+        let up = y1*se + p[2]*ce;
+        let s = VIEW_H * 0.3 * self.zoom;
+        Projected {
+            screen: Point::xy(VIEW_W/2.0 + x1*s, VIEW_H/2.0 - up*s),
+            depth, view: [x1, depth, up]
+        }
+    }
+
+    /// Draw the surface as seen from the current direction: a box around it, each cell of the
+    /// grid shaded by its height and how squarely it faces the light and outlined to show the
+    /// mesh, painted back to front so that nearer parts cover farther ones
+    pub fn figure(&self) -> Figure {
+        let (z0, z1) = self.z_range();
+        let rows = self.z.len();
+        let cols = self.z[0].len();
+        let cube = |i: usize, j: usize, z: f32| [
+            -1.0 + 2.0 * i as f32 / (cols - 1) as f32,
+            -1.0 + 2.0 * j as f32 / (rows - 1) as f32,
+            -0.7 + 1.4 * (z - z0) / (z1 - z0)
+        ];
+        let mut shapes = Vec::new();
+        let edge = Rgb(0.6, 0.6, 0.6);
+        let black = Rgb(0.0, 0.0, 0.0);
+
+        // the floor of the box and the post at its back corner go behind the surface
+        let corners: Vec<Projected> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter()
+            .map(|&(x, y)| self.project([x, y, -0.7])).collect();
+        let mut floor: Vec<Point> = corners.iter().map(|c| c.screen).collect();
+        floor.push(floor[0]);
+        shapes.push(Shape::Line { points: floor, color: edge, width: 1.0 });
+        let back = (0..4).max_by(|&a, &b| corners[a].depth.partial_cmp(&corners[b].depth).unwrap_or(::std::cmp::Ordering::Equal)).unwrap();
+        let (bx, by) = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)][back];
+        shapes.push(Shape::Line { points: vec![corners[back].screen, self.project([bx, by, 0.7]).screen], color: edge, width: 1.0 });
+
+        let mut quads = Vec::new();
+        for j in 0..rows-1 {
+            for i in 0..cols-1 {
+                let zs = [self.z[j][i], self.z[j][i+1], self.z[j+1][i+1], self.z[j+1][i]];
+                if zs.iter().any(|z| z.is_none()) { continue; }
+                let idx = [(i, j), (i+1, j), (i+1, j+1), (i, j+1)];
+                let pts: Vec<Projected> = idx.iter().zip(zs.iter())
+                    .map(|(&(i, j), z)| self.project(cube(i, j, z.unwrap()))).collect();
+                let depth = pts.iter().map(|p| p.depth).sum::<f32>() / 4.0;
+                let mean_z = zs.iter().map(|z| z.unwrap()).sum::<f32>() / 4.0;
+                // light from over the viewer's shoulder, on whichever side of the surface faces us
+                let n = cross(sub(pts[2].view, pts[0].view), sub(pts[3].view, pts[1].view));
+                let len = (n[0]*n[0] + n[1]*n[1] + n[2]*n[2]).sqrt().max(1e-9);
+                let light = [-0.3, -0.81, 0.51];
+                let lambert = ((n[0]*light[0] + n[1]*light[1] + n[2]*light[2]) / len).abs().min(1.0);
+                let base = height_color((mean_z - z0) / (z1 - z0));
+                let shade = 0.35 + 0.65 * lambert;
+                quads.push((depth, pts, Rgb(base.0 * shade, base.1 * shade, base.2 * shade)));
+            }
+        }
+        quads.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(::std::cmp::Ordering::Equal));
+        for (_, pts, color) in quads {
+            let mut outline: Vec<Point> = pts.iter().map(|p| p.screen).collect();
+            outline.push(outline[0]);
+            shapes.push(Shape::Fill { points: outline.clone(), color });
+            shapes.push(Shape::Line { points: outline, color: Rgb(color.0 * 0.5, color.1 * 0.5, color.2 * 0.5), width: 0.5 });
+        }
+
+        // label the ends of the axes along the floor, and the height range
+        let text = |at: Point, s: String, anchor: Anchor| Shape::Text { at, text: s, anchor, size: 12.0, color: black };
+        let (x0, x1) = self.x_range;
+        let (y0, y1) = self.y_range;
+        let px = self.project([1.15, -1.0, -0.7]).screen;
+        let py = self.project([-1.0, 1.15, -0.7]).screen;
+        shapes.push(text(px, format!("{} = {}", self.axes.0, short(x1)), Anchor::Middle));
+        shapes.push(text(self.project([-1.0, -1.15, -0.7]).screen, format!("{}, {}", short(x0), short(y0)), Anchor::Middle));
+        shapes.push(text(py, format!("{} = {}", self.axes.1, short(y1)), Anchor::Middle));
+        shapes.push(text(Point::xy(12.0, VIEW_H - 12.0), format!("z from {} to {}", short(z0), short(z1)), Anchor::Start));
+        let title = self.title.clone().unwrap_or_else(|| self.label.clone());
+        shapes.push(Shape::Text { at: Point::xy(VIEW_W / 2.0, 22.0), text: title, anchor: Anchor::Middle, size: 14.0, color: black });
+        Figure::new(VIEW_W, VIEW_H, shapes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Surface;
+    use plot::Shape;
+
+    const BOWL: &'static str = "title|bowl\nlabel|x^2+y^2\naxes|u|v\ngrid|2|-1.0|1.0|0.0|2.0\n\
+                                r nan 1.0 2.0\nr 1.0 0.0 1.0\nr 2.0 1.0 2.0\n";
+
+    #[test]
+    fn parse() {
+        let s = Surface::parse(BOWL).unwrap();
+        assert_eq!(s.title, Some(String::from("bowl")));
+        assert_eq!(s.label, "x^2+y^2");
+        assert_eq!(s.axes, (String::from("u"), String::from("v")));
+        assert_eq!((s.x_range, s.y_range), ((-1.0, 1.0), (0.0, 2.0)));
+        assert_eq!(s.z[0], vec![None, Some(1.0), Some(2.0)]);
+        assert_eq!(s.z[1][1], Some(0.0));
+        // too few rows or ragged ones aren't a surface
+        assert!(Surface::parse("grid|1|0|1|0|1\nr 1 2\n").is_none());
+        assert!(Surface::parse("grid|1|0|1|0|1\nr 1 2\nr 1\n").is_none());
+    }
+
+    #[test]
+    fn gaps() {
+        // the cell with a corner where the function isn't real is left out
+        let fig = Surface::parse(BOWL).unwrap().figure();
+        let fills = fig.shapes.iter().filter(|s| match **s { Shape::Fill { .. } => true, _ => false }).count();
+        assert_eq!(fills, 3);
+    }
+}
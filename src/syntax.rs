@@ -0,0 +1,347 @@
+use runic::Color;
+
+use std::io::{self, BufRead, BufReader};
+use std::fs::File;
+use std::path::Path;
+use std::ops::Range;
+
+/// The kinds of token that input is highlighted by
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TokenKind {
+    Comment, String, Number, Keyword, Operator, Bracket,
+    /// `%pi`, `true`, `inf` and other `%` names
+    Constant,
+    /// the `:`/`:=` family, and the name being defined by one
+    Definition,
+    Identifier
+}
+
+pub struct Token {
+    pub kind: TokenKind,
+    /// byte range in the source text
+    pub range: Range<usize>,
+    /// false for a string or comment that runs to the end of the text without being closed
+    pub closed: bool
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "if", "then", "else", "elseif", "for", "from", "step", "thru", "while", "unless", "do", "in",
+    "and", "or", "not", "block", "lambda", "return", "go"
+];
+
+const CONSTANTS: &'static [&'static str] = &[
+    "true", "false", "inf", "minf", "infinity", "und", "ind", "zeroa", "zerob"
+];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '%'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '%'
+}
+
+/// Split Maxima input into tokens for highlighting. Whitespace is skipped, and anything
+/// unterminated, like a string or comment left open, runs to the end of the text
+pub fn lex(text: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut closed = true;
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '/' if chars.peek().map(|&(_, n)| n) == Some('*') => {
+                chars.next();
+                let mut last = ' ';
+                closed = false;
+                while let Some((_, c)) = chars.next() {
+                    if last == '*' && c == '/' { closed = true; break; }
+                    last = c;
+                }
+                TokenKind::Comment
+            },
+            '"' => {
+                closed = false;
+                while let Some((_, c)) = chars.next() {
+                    if c == '\\' { chars.next(); } else if c == '"' { closed = true; break; }
+                }
+                TokenKind::String
+            },
+            c if c.is_ascii_digit() || (c == '.' && chars.peek().map_or(false, |&(_, n)| n.is_ascii_digit())) => {
+                let mut prev = c;
+                while let Some(&(_, n)) = chars.peek() {
+                    let exponent_sign = (n == '+' || n == '-') && "eEbBdD".contains(prev);
+                    if n.is_ascii_digit() || n == '.' || "eEbBdD".contains(n) || exponent_sign {
+                        chars.next();
+                        prev = n;
+                    } else {
+                        break;
+                    }
+                }
+                TokenKind::Number
+            },
+            c if is_ident_start(c) => {
+                while chars.peek().map_or(false, |&(_, n)| is_ident_char(n)) { chars.next(); }
+                let end = chars.peek().map(|&(i, _)| i).unwrap_or(text.len());
+                let word = &text[start..end];
+                if KEYWORDS.contains(&word) {
+                    TokenKind::Keyword
+                } else if word.starts_with('%') || CONSTANTS.contains(&word) {
+                    TokenKind::Constant
+                } else {
+                    TokenKind::Identifier
+                }
+            },
+            '(' | ')' | '[' | ']' | '{' | '}' => TokenKind::Bracket,
+            ':' => {
+                if chars.peek().map(|&(_, n)| n) == Some(':') { chars.next(); }
+                if chars.peek().map(|&(_, n)| n) == Some('=') { chars.next(); }
+                mark_definition(&mut tokens, text);
+                TokenKind::Definition
+            },
+            _ => {
+                // operators made of two characters
+                let next = chars.peek().map(|&(_, n)| n);
+                match (c, next) {
+                    ('*', Some('*')) | ('<', Some('=')) | ('>', Some('=')) | ('\'', Some('\'')) => { chars.next(); },
+                    _ => {}
+                }
+                TokenKind::Operator
+            }
+        };
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or(text.len());
+        tokens.push(Token { kind, range: start..end, closed });
+    }
+    tokens
+}
+
+/// mark the name in front of a definition operator: `x` in `x: 1`, or `f` in `f(x) := ...`
+fn mark_definition(tokens: &mut Vec<Token>, text: &str) {
+    let mut i = match tokens.len() { 0 => return, n => n - 1 };
+    if tokens[i].kind == TokenKind::Bracket && &text[tokens[i].range.clone()] == ")" {
+        let mut depth = 0;
+        loop {
+            match &text[tokens[i].range.clone()] {
+                ")" => depth += 1,
+                "(" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 { break; }
+            if i == 0 { return; }
+            i -= 1;
+        }
+        if i == 0 { return; }
+        i -= 1;
+    }
+    if tokens[i].kind == TokenKind::Identifier {
+        tokens[i].kind = TokenKind::Definition;
+    }
+}
+
+/// The colors each kind of token is drawn in. Kinds without a color use the text color
+#[derive(Clone)]
+pub struct Palette {
+    pub comment: Option<Color>,
+    pub string: Option<Color>,
+    pub number: Option<Color>,
+    pub keyword: Option<Color>,
+    pub operator: Option<Color>,
+    pub bracket: Option<Color>,
+    pub constant: Option<Color>,
+    pub definition: Option<Color>,
+    pub identifier: Option<Color>
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    let s = s.trim().trim_left_matches('#');
+    if s.len() != 6 { return None; }
+    let channel = |i: usize| u8::from_str_radix(&s[i..i+2], 16).ok().map(|v| v as f32 / 255.0);
+    Some(Color::rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+impl Palette {
+    /// colors that suit the dark notebook background
+    pub fn dark() -> Palette {
+        Palette {
+            comment: Some(Color::rgb(0.45, 0.5, 0.45)),
+            string: Some(Color::rgb(0.8, 0.6, 0.4)),
+            number: Some(Color::rgb(0.6, 0.75, 0.9)),
+            keyword: Some(Color::rgb(0.75, 0.55, 0.85)),
+            operator: Some(Color::rgb(0.65, 0.65, 0.7)),
+            bracket: None,
+            constant: Some(Color::rgb(0.5, 0.8, 0.75)),
+            definition: Some(Color::rgb(0.9, 0.8, 0.45)),
+            identifier: None
+        }
+    }
+
+    pub fn color(&self, kind: TokenKind) -> Option<Color> {
+        match kind {
+            TokenKind::Comment => self.comment,
+            TokenKind::String => self.string,
+            TokenKind::Number => self.number,
+            TokenKind::Keyword => self.keyword,
+            TokenKind::Operator => self.operator,
+            TokenKind::Bracket => self.bracket,
+            TokenKind::Constant => self.constant,
+            TokenKind::Definition => self.definition,
+            TokenKind::Identifier => self.identifier
+        }
+    }
+
+    /// Read a theme file of `kind = #rrggbb` lines over the dark palette. A kind set to `none`
+    /// goes back to the text color, and lines starting with `#` are comments
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Palette> {
+        let bad = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut palette = Palette::dark();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let mut parts = line.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(n), Some(v)) => (n.trim(), v.trim()),
+                _ => return Err(bad(format!("expected `kind = #rrggbb`: {}", line)))
+            };
+            let color = if value == "none" {
+                None
+            } else {
+                Some(parse_color(value).ok_or_else(|| bad(format!("bad color: {}", value)))?)
+            };
+            match name {
+                "comment" => palette.comment = color,
+                "string" => palette.string = color,
+                "number" => palette.number = color,
+                "keyword" => palette.keyword = color,
+                "operator" => palette.operator = color,
+                "bracket" => palette.bracket = color,
+                "constant" => palette.constant = color,
+                "definition" => palette.definition = color,
+                "identifier" => palette.identifier = color,
+                _ => return Err(bad(format!("unknown token kind: {}", name)))
+            }
+        }
+        Ok(palette)
+    }
+}
+
+/// What is wrong with the brackets or quotes of some input. Positions are byte offsets
+#[derive(Debug)]
+pub enum Imbalance {
+    Unclosed(char, usize),
+    Unopened(char, usize),
+    Mismatched { open: char, close: char, at: usize },
+    UnterminatedString(usize),
+    UnterminatedComment(usize)
+}
+
+impl Imbalance {
+    pub fn position(&self) -> usize {
+        match *self {
+            Imbalance::Unclosed(_, p) | Imbalance::Unopened(_, p) |
+            Imbalance::UnterminatedString(p) | Imbalance::UnterminatedComment(p) => p,
+            Imbalance::Mismatched { at, .. } => at
+        }
+    }
+}
+
+impl ::std::fmt::Display for Imbalance {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Imbalance::Unclosed(c, _) => write!(fmt, "`{}` is never closed", c),
+            Imbalance::Unopened(c, _) => write!(fmt, "`{}` has nothing to close", c),
+            Imbalance::Mismatched { open, close, .. } => write!(fmt, "`{}` is closed by `{}`", open, close),
+            Imbalance::UnterminatedString(_) => write!(fmt, "string is never closed"),
+            Imbalance::UnterminatedComment(_) => write!(fmt, "comment is never closed")
+        }
+    }
+}
+
+fn closer(open: char) -> char {
+    match open { '(' => ')', '[' => ']', _ => '}' }
+}
+
+/// check that every bracket, string and comment in the input is closed properly
+pub fn check_balance(text: &str) -> Result<(), Imbalance> {
+    let mut stack: Vec<(char, usize)> = Vec::new();
+    for tok in lex(text) {
+        let s = &text[tok.range.clone()];
+        match tok.kind {
+            TokenKind::String if !tok.closed => return Err(Imbalance::UnterminatedString(tok.range.start)),
+            TokenKind::Comment if !tok.closed => return Err(Imbalance::UnterminatedComment(tok.range.start)),
+            TokenKind::Bracket => {
+                let c = s.chars().next().unwrap();
+                match c {
+                    '(' | '[' | '{' => stack.push((c, tok.range.start)),
+                    _ => match stack.pop() {
+                        Some((open, _)) if closer(open) == c => {},
+                        Some((open, _)) => return Err(Imbalance::Mismatched { open, close: c, at: tok.range.start }),
+                        None => return Err(Imbalance::Unopened(c, tok.range.start))
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    match stack.pop() {
+        Some((c, at)) => Err(Imbalance::Unclosed(c, at)),
+        None => Ok(())
+    }
+}
+
+/// The bracket just after the caret at `pos`, or failing that just before it, and the bracket
+/// matching it, as byte offsets. Brackets in strings and comments don't count
+pub fn matching_bracket(text: &str, pos: usize) -> Option<(usize, usize)> {
+    let tokens: Vec<Token> = lex(text).into_iter().filter(|t| t.kind == TokenKind::Bracket).collect();
+    let at = tokens.iter().find(|t| t.range.start == pos)
+        .or_else(|| tokens.iter().find(|t| t.range.end == pos))?.range.start;
+    let opening = |t: &Token| "([{".contains(&text[t.range.clone()]);
+    let i = tokens.iter().position(|t| t.range.start == at)?;
+    let mut depth = 0i32;
+    if opening(&tokens[i]) {
+        for t in &tokens[i..] {
+            depth += if opening(t) { 1 } else { -1 };
+            if depth == 0 { return Some((at, t.range.start)); }
+        }
+    } else {
+        for t in tokens[..i+1].iter().rev() {
+            depth += if opening(t) { -1 } else { 1 };
+            if depth == 0 { return Some((at, t.range.start)); }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_balance, lex, Imbalance, TokenKind};
+
+    #[test]
+    fn unterminated() {
+        assert!(check_balance("f(\"a\\\"b\", /* c */ [1])").is_ok());
+        // the closing quote is escaped, so the string runs on to the end
+        match check_balance("\"a\\\"") {
+            Err(Imbalance::UnterminatedString(0)) => {},
+            r => panic!("{:?}", r)
+        }
+        match check_balance("x /* a */ + /*/") {
+            Err(Imbalance::UnterminatedComment(12)) => {},
+            r => panic!("{:?}", r)
+        }
+    }
+
+    #[test]
+    fn brackets() {
+        match check_balance("f([x)]") {
+            Err(Imbalance::Mismatched { open: '[', close: ')', at: 4 }) => {},
+            r => panic!("{:?}", r)
+        }
+        match check_balance("(x, \")\"") {
+            Err(Imbalance::Unclosed('(', 0)) => {},
+            r => panic!("{:?}", r)
+        }
+        let kinds: Vec<TokenKind> = lex("f(x) := 2").iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![TokenKind::Definition, TokenKind::Bracket, TokenKind::Identifier, TokenKind::Bracket,
+                               TokenKind::Definition, TokenKind::Number]);
+    }
+}